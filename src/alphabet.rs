@@ -0,0 +1,96 @@
+//! The letters of a language, used where text is split into words.
+
+/// The upper and lower case letters of a lang, where `upper[i]` and
+/// `lower[i]` are the same letter. An empty alphabet accepts every Unicode
+/// letter, for langs which were added before their letters were recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Alphabet {
+    upper: Vec<char>,
+    lower: Vec<char>,
+}
+
+impl Alphabet {
+    /// Creates an alphabet from matching upper and lower case letters. If
+    /// `lower` does not match `upper`, it is derived from `upper`.
+    pub fn new(upper: &str, lower: &str) -> Self {
+        let upper = upper.chars().collect::<Vec<_>>();
+        let mut lower = lower.chars().collect::<Vec<_>>();
+
+        if lower.len() != upper.len() {
+            lower = upper
+                .iter()
+                .map(|c| c.to_lowercase().next().unwrap_or(*c))
+                .collect();
+        }
+
+        Self { upper, lower }
+    }
+
+    /// The lower case form of `c`, if it is a letter.
+    pub fn to_lower(&self, c: char) -> Option<char> {
+        if self.upper.is_empty() {
+            return match c.is_alphabetic() {
+                true => c.to_lowercase().next(),
+                false => None,
+            };
+        }
+
+        match self.upper.iter().position(|&u| u == c) {
+            Some(i) => Some(self.lower[i]),
+            None => self.lower.contains(&c).then_some(c),
+        }
+    }
+
+    /// The upper and lower case letters, which are empty for any letter.
+    pub fn letters(&self) -> (String, String) {
+        (self.upper.iter().collect(), self.lower.iter().collect())
+    }
+
+    pub fn is_letter(&self, c: char) -> bool {
+        self.to_lower(c).is_some()
+    }
+
+    /// Whether the alphabet has no letters of its own, so accepts any.
+    pub fn is_any(&self) -> bool {
+        self.upper.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_case_within_alphabet() {
+        let german = Alphabet::new(
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÜẞ",
+            "abcdefghijklmnopqrstuvwxyzäöüß",
+        );
+
+        assert_eq!(german.to_lower('Ä'), Some('ä'));
+        assert_eq!(german.to_lower('ẞ'), Some('ß'));
+        assert_eq!(german.to_lower('ß'), Some('ß'));
+        assert_eq!(german.to_lower('é'), None);
+        assert_eq!(german.to_lower('Σ'), None);
+    }
+
+    #[test]
+    fn derives_lower_case_letters() {
+        let greek = Alphabet::new("ΑΒΓΔΕΖΗΘΙΚΛΜΝΞΟΠΡΣΤΥΦΧΨΩ", "");
+
+        assert_eq!(greek.to_lower('Σ'), Some('σ'));
+        assert_eq!(greek.to_lower('ω'), Some('ω'));
+        assert_eq!(greek.to_lower('A'), None);
+    }
+
+    #[test]
+    fn any_letter_without_alphabet() {
+        let any = Alphabet::default();
+
+        assert_eq!(any.to_lower('Ω'), Some('ω'));
+        assert_eq!(any.to_lower('ẞ'), Some('ß'));
+        assert_eq!(any.to_lower('É'), Some('é'));
+        assert_eq!(any.to_lower('3'), None);
+        assert_eq!(any.to_lower('-'), None);
+    }
+}
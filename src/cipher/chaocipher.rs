@@ -0,0 +1,617 @@
+//! The Chaocipher, invented by John F. Byrne in 1918. Two alphabets are
+//! permuted after every letter, so the cipher cannot be expressed as a
+//! combination of the simpler ciphers.
+
+use crate::{error::CipherError, restart::Search, score::Stats};
+use classic_crypto::lang::Lang;
+use rayon::prelude::*;
+use std::fmt;
+
+/// Upper bound on the number of wheel assignments explored by the crib
+/// attack, shared between the crib positions which are tried.
+const MAX_NODES: usize = 50_000_000;
+/// Number of crib letters around each letter which are compared when
+/// choosing where to start the search.
+const START_WINDOW: usize = 8;
+/// Number of crib letters which the search may be started from.
+const MAX_STARTS: usize = 4;
+/// Number of wheel assignments first explored from each starting letter at
+/// each crib position. This grows until the crib is placed.
+const MIN_START_NODES: usize = 1_000;
+/// Upper bound on the number of complete wheel assignments which are scored
+/// at each crib position.
+const MAX_CANDIDATES: usize = 1_000;
+
+/// The Chaocipher. The left alphabet is the ciphertext alphabet and the right
+/// alphabet is the plaintext alphabet.
+#[derive(Debug, Clone)]
+pub struct Chaocipher {
+    left: Vec<char>,
+    right: Vec<char>,
+}
+
+/// Parameters for the crib based attack on the Chaocipher.
+pub struct ChaocipherSolve<'a> {
+    /// Known plaintext.
+    pub crib: &'a str,
+    /// The position of the crib, counted in letters of `alphabet`. If `None`
+    /// then every position is tried, sharing the search's budget.
+    pub crib_pos: Option<usize>,
+    /// The letters which make up both alphabets.
+    pub alphabet: &'a str,
+    /// Statistics used to rank candidate solutions.
+    pub stats: &'a Stats,
+    /// The search stops with an error if this expires before the crib has
+    /// been placed.
+    pub search: &'a Search,
+}
+
+impl Chaocipher {
+    /// Creates a Chaocipher from its left (ciphertext) and right (plaintext)
+    /// starting alphabets. Both alphabets must contain the same letters
+    /// exactly once.
+    pub fn new(left: &str, right: &str) -> anyhow::Result<Self> {
+        let left = left.chars().map(upper).collect::<Vec<_>>();
+        let right = right.chars().map(upper).collect::<Vec<_>>();
+
+        let mut sorted_left = left.clone();
+        let mut sorted_right = right.clone();
+        sorted_left.sort_unstable();
+        sorted_right.sort_unstable();
+        sorted_left.dedup();
+
+        if left.len() < 4 || sorted_left.len() != left.len() || sorted_left != sorted_right {
+            return Err(CipherError::InvalidAlphabet.into());
+        }
+
+        Ok(Self { left, right })
+    }
+
+    /// Encrypts or decrypts `msg`. Characters outside of the alphabets are
+    /// copied to the output and do not advance the wheels.
+    fn crypt(&self, msg: &str, encrypt: bool) -> String {
+        let mut left = self.left.clone();
+        let mut right = self.right.clone();
+
+        msg.chars()
+            .map(|c| {
+                let (from, to) = match encrypt {
+                    true => (&right, &left),
+                    false => (&left, &right),
+                };
+
+                match from.iter().position(|&x| x == upper(c)) {
+                    Some(idx) => {
+                        let out = to[idx];
+                        permute(&mut left, &mut right, idx);
+                        out
+                    }
+                    None => c,
+                }
+            })
+            .collect()
+    }
+
+    /// Recovers both starting alphabets from a crib using Byrne's known
+    /// plaintext attack, returning the candidate whose decryption scores
+    /// best.
+    ///
+    /// Each crib letter pins its ciphertext letter and plaintext letter to
+    /// the same position on the two wheels. Positions are searched depth
+    /// first, and the wheels are permuted exactly as during encryption, so a
+    /// sufficiently long crib (around 80 letters or more) fixes most of both
+    /// alphabets. Letters which are never constrained are filled in
+    /// alphabetical order.
+    pub fn solve(lang: &Lang, msg: &str, params: ChaocipherSolve) -> anyhow::Result<Self> {
+        let ChaocipherSolve {
+            crib,
+            crib_pos,
+            alphabet,
+            stats,
+            search,
+        } = params;
+
+        let alphabet = alphabet.chars().map(upper).collect::<Vec<_>>();
+        let index = |c: char| alphabet.iter().position(|&x| x == upper(c));
+        let ct = msg.chars().filter_map(index).collect::<Vec<_>>();
+        let crib = crib.chars().filter_map(index).collect::<Vec<_>>();
+
+        if alphabet.len() < 4 {
+            return Err(CipherError::InvalidAlphabet.into());
+        }
+        if crib.is_empty() {
+            return Err(CipherError::MissingCrib.into());
+        }
+        if crib.len() > ct.len() {
+            return Err(CipherError::CribInconsistent.into());
+        }
+
+        let positions = match crib_pos {
+            Some(pos) if pos + crib.len() <= ct.len() => pos..pos + 1,
+            Some(_) => return Err(CipherError::CribInconsistent.into()),
+            None => 0..ct.len() - crib.len() + 1,
+        };
+
+        // positions are searched with a growing budget, so that the time
+        // spent on each is similar, until the crib is found to fit at least
+        // one of them
+        let mut pending = positions.collect::<Vec<_>>();
+        let mut found = Vec::new();
+        let mut budget = MIN_START_NODES;
+        let mut spent = 0;
+
+        while found.is_empty() && !pending.is_empty() {
+            if spent >= MAX_NODES || search.expired() {
+                return Err(CipherError::CribSearchLimit.into());
+            }
+
+            let searched = pending
+                .par_iter()
+                .map(|&pos| {
+                    (
+                        pos,
+                        crib_search(alphabet.len(), &ct[pos..][..crib.len()], &crib, budget),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            pending.clear();
+            for (pos, search) in searched {
+                spent += search.nodes;
+
+                match (search.candidates.is_empty(), search.finished) {
+                    (false, _) => found.push((pos, search.candidates)),
+                    // the crib cannot be at this position
+                    (true, true) => {}
+                    (true, false) => pending.push(pos),
+                }
+            }
+
+            budget *= 4;
+        }
+
+        let mut best: Option<(f32, Chaocipher)> = None;
+
+        for (pos, candidates) in found {
+            for (left, right) in candidates {
+                let (mut left, mut right) = (fill(left), fill(right));
+
+                // walk the wheels back to the start of the message
+                for &c in ct[..pos].iter().rev() {
+                    unpermute(&mut left, &mut right, c);
+                }
+
+                let cipher = Chaocipher {
+                    left: left.into_iter().map(|i| alphabet[i]).collect(),
+                    right: right.into_iter().map(|i| alphabet[i]).collect(),
+                };
+                let plain = cipher.decrypt(msg);
+                let score = stats.score(lang, &plain);
+
+                match &best {
+                    Some((best_score, _)) if *best_score >= score => {}
+                    _ => best = Some((score, cipher)),
+                }
+            }
+        }
+
+        best.map(|(_, cipher)| cipher)
+            .ok_or_else(|| CipherError::CribInconsistent.into())
+    }
+
+    pub fn encrypt(&self, msg: &str) -> String {
+        self.crypt(msg, true)
+    }
+
+    pub fn decrypt(&self, msg: &str) -> String {
+        self.crypt(msg, false)
+    }
+}
+
+impl fmt::Display for Chaocipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "left:  {}", self.left.iter().collect::<String>())?;
+        write!(f, "right: {}", self.right.iter().collect::<String>())
+    }
+}
+
+/// A partially recovered wheel, indexed by slot.
+type Wheel = Vec<Option<usize>>;
+
+/// The wheel assignments found by searching for the position of a crib.
+struct CribFit {
+    /// The starting wheels, by position, of each assignment which fits the
+    /// whole crib.
+    candidates: Vec<(Wheel, Wheel)>,
+    /// Whether every assignment was explored, so that there are no more
+    /// candidates.
+    finished: bool,
+    nodes: usize,
+}
+
+/// Searches for the wheel assignments which fit the crib `pt` to the
+/// ciphertext `ct`, exploring at most `budget` assignments from each
+/// starting letter.
+///
+/// How quickly the search finishes depends greatly on the crib letter it
+/// starts from, so it is started from the letters with the fewest distinct
+/// letters around them in turn, until one finishes or finds a candidate.
+fn crib_search(len: usize, ct: &[usize], pt: &[usize], budget: usize) -> CribFit {
+    let window = START_WINDOW.min(ct.len());
+    let distinct = |letters: &[usize]| {
+        let mut seen = vec![false; len];
+        letters
+            .iter()
+            .filter(|&&x| !std::mem::replace(&mut seen[x], true))
+            .count()
+    };
+
+    let mut starts = (0..=ct.len() - window).collect::<Vec<_>>();
+    starts.sort_by_key(|&i| distinct(&ct[i..i + window]) + distinct(&pt[i..i + window]));
+    starts.truncate(MAX_STARTS);
+
+    let mut nodes = 0;
+
+    for first in starts {
+        let mut search = CribSearch::new(len, ct, pt, first, budget);
+        search.run();
+        nodes += search.nodes;
+
+        let finished = search.nodes < budget;
+        if finished || !search.candidates.is_empty() {
+            return CribFit {
+                candidates: search.candidates,
+                finished,
+                nodes,
+            };
+        }
+    }
+
+    CribFit {
+        candidates: Vec::new(),
+        finished: false,
+        nodes,
+    }
+}
+
+/// Depth first search over the positions of crib letters on the two wheels.
+///
+/// The crib letters which have been placed form a window, which is extended
+/// at whichever end has the fewest possible positions, so that contradictions
+/// are found as early as possible. Slots are identified by their position on
+/// the wheels before the first letter placed, so `left[slot]` is the letter
+/// (if known) which started at `slot`.
+struct CribSearch<'a> {
+    ct: &'a [usize],
+    pt: &'a [usize],
+    left: Wheel,
+    right: Wheel,
+    /// The slot of each letter on each wheel, if it has been placed.
+    left_slot: Vec<Option<usize>>,
+    right_slot: Vec<Option<usize>>,
+    /// The crib letters from `start` up to `end` have been placed.
+    start: usize,
+    end: usize,
+    /// The slots at each position of the wheels before the crib letter at
+    /// `start`.
+    back: (Vec<usize>, Vec<usize>),
+    /// The slots at each position of the wheels before the crib letter at
+    /// `end`.
+    front: (Vec<usize>, Vec<usize>),
+    /// Reused lists of positions, one for each depth.
+    positions: Vec<Vec<usize>>,
+    nodes: usize,
+    max_nodes: usize,
+    /// The starting wheels, by position, of each assignment which fits the
+    /// whole crib.
+    candidates: Vec<(Wheel, Wheel)>,
+}
+
+impl<'a> CribSearch<'a> {
+    /// Creates a search which starts by placing the crib letter at `first`.
+    fn new(len: usize, ct: &'a [usize], pt: &'a [usize], first: usize, max_nodes: usize) -> Self {
+        Self {
+            ct,
+            pt,
+            left: vec![None; len],
+            right: vec![None; len],
+            left_slot: vec![None; len],
+            right_slot: vec![None; len],
+            start: first,
+            end: first,
+            back: ((0..len).collect(), (0..len).collect()),
+            front: ((0..len).collect(), (0..len).collect()),
+            positions: vec![Vec::with_capacity(len); ct.len() + 1],
+            nodes: 0,
+            max_nodes,
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Finds the positions on the left wheel at which the crib letter after
+    /// the window (if `forward`) or before it may sit.
+    ///
+    /// Before a letter is enciphered, its ciphertext and plaintext letters
+    /// are at the same position. After it, the ciphertext letter is at the
+    /// zenith of the left wheel and the plaintext letter is one place before
+    /// the zenith of the right wheel, so when extending the window backwards
+    /// the plaintext letter is one place before the ciphertext letter.
+    fn positions(&self, forward: bool, positions: &mut Vec<usize>) {
+        let len = self.left.len();
+        let (i, (left_slots, right_slots), before) = match forward {
+            true => (self.end, &self.front, 0),
+            false => (self.start - 1, &self.back, 1),
+        };
+        let right_at = |k: usize| (k + len - before) % len;
+        let position = |slots: &[usize], slot| slots.iter().position(|&s| s == slot);
+
+        let left_pos = self.left_slot[self.ct[i]].and_then(|slot| position(left_slots, slot));
+        let right_pos = self.right_slot[self.pt[i]]
+            .and_then(|slot| position(right_slots, slot))
+            .map(|r| (r + before) % len);
+        // either the letter is already at `k`, or it is unplaced and `k` is
+        // empty
+        let fits = |k: usize| {
+            left_pos.map_or(self.left[left_slots[k]].is_none(), |l| l == k)
+                && right_pos.map_or(self.right[right_slots[right_at(k)]].is_none(), |r| r == k)
+        };
+
+        positions.clear();
+        match (left_pos, right_pos) {
+            // the wheels may be rotated freely before the first letter
+            _ if self.start == self.end => positions.push(0),
+            (Some(k), _) | (None, Some(k)) => positions.extend(Some(k).filter(|&k| fits(k))),
+            (None, None) => positions.extend((0..len).filter(|&k| fits(k))),
+        }
+    }
+
+    fn run(&mut self) {
+        if self.nodes >= self.max_nodes || self.candidates.len() >= MAX_CANDIDATES {
+            return;
+        }
+
+        if self.start == 0 && self.end == self.ct.len() {
+            let (left, right) = &self.back;
+            self.candidates.push((
+                left.iter().map(|&s| self.left[s]).collect(),
+                right.iter().map(|&s| self.right[s]).collect(),
+            ));
+            return;
+        }
+
+        let depth = self.end - self.start;
+        let mut positions = std::mem::take(&mut self.positions[depth]);
+        let mut other = std::mem::take(&mut self.positions[depth + 1]);
+
+        // a forced position is taken without looking at the other end
+        let forward = match (self.end < self.ct.len(), self.start > 0) {
+            (true, true) => {
+                self.positions(true, &mut positions);
+
+                if positions.len() > 1 {
+                    self.positions(false, &mut other);
+                }
+
+                match positions.len() > 1 && other.len() < positions.len() {
+                    true => {
+                        std::mem::swap(&mut positions, &mut other);
+                        false
+                    }
+                    false => true,
+                }
+            }
+            (forward, _) => {
+                self.positions(forward, &mut positions);
+                forward
+            }
+        };
+        self.positions[depth + 1] = other;
+
+        let len = self.left.len();
+        let i = match forward {
+            true => self.end,
+            false => self.start - 1,
+        };
+        let (c, p) = (self.ct[i], self.pt[i]);
+
+        for &k in &positions {
+            self.nodes += 1;
+
+            let (left_slot, right_slot) = match forward {
+                true => (self.front.0[k], self.front.1[k]),
+                false => (self.back.0[k], self.back.1[(k + len - 1) % len]),
+            };
+            // the letters may have been placed already
+            let placed = (
+                self.left[left_slot].is_some(),
+                self.right[right_slot].is_some(),
+            );
+
+            self.left[left_slot] = Some(c);
+            self.right[right_slot] = Some(p);
+            self.left_slot[c] = Some(left_slot);
+            self.right_slot[p] = Some(right_slot);
+
+            match forward {
+                true => {
+                    let (left, right) = &mut self.front;
+                    permute(left, right, k);
+                    self.end += 1;
+                }
+                false => {
+                    // rotate the letter to the zenith, then undo the step
+                    let (left, right) = &mut self.back;
+                    left.rotate_left(k);
+                    right.rotate_left(k);
+                    unpermute_at(left, right, 0);
+                    self.start -= 1;
+                }
+            }
+
+            self.run();
+
+            match forward {
+                true => {
+                    let (left, right) = &mut self.front;
+                    unpermute_at(left, right, k);
+                    self.end -= 1;
+                }
+                false => {
+                    let (left, right) = &mut self.back;
+                    permute(left, right, 0);
+                    left.rotate_right(k);
+                    right.rotate_right(k);
+                    self.start += 1;
+                }
+            }
+
+            if !placed.0 {
+                self.left[left_slot] = None;
+                self.left_slot[c] = None;
+            }
+            if !placed.1 {
+                self.right[right_slot] = None;
+                self.right_slot[p] = None;
+            }
+        }
+
+        self.positions[depth] = positions;
+    }
+}
+
+/// Permutes the wheels after the letter at `idx` has been used.
+fn permute<T>(left: &mut [T], right: &mut [T], idx: usize) {
+    let nadir = left.len() / 2;
+
+    // bring the used letter to the zenith, then move the letter after it to
+    // the nadir
+    left.rotate_left(idx);
+    left[1..=nadir].rotate_left(1);
+
+    // bring the letter after the used letter to the zenith, then move the
+    // letter two places after the zenith to the nadir
+    right.rotate_left((idx + 1) % right.len());
+    right[2..=nadir].rotate_left(1);
+}
+
+/// Exactly reverses `permute` for the letter at `idx`.
+fn unpermute_at<T>(left: &mut [T], right: &mut [T], idx: usize) {
+    let nadir = left.len() / 2;
+
+    left[1..=nadir].rotate_right(1);
+    left.rotate_right(idx);
+
+    right[2..=nadir].rotate_right(1);
+    right.rotate_right((idx + 1) % right.len());
+}
+
+/// Reverses `permute` for the step which produced the ciphertext letter `c`,
+/// up to a rotation shared by both wheels (which does not affect
+/// encryption).
+fn unpermute<T: PartialEq>(left: &mut [T], right: &mut [T], c: T) {
+    let nadir = left.len() / 2;
+
+    // `permute` leaves the used letter at the zenith of the left wheel
+    if let Some(idx) = left.iter().position(|x| *x == c) {
+        left.rotate_left(idx);
+        right.rotate_left(idx);
+    }
+
+    left[1..=nadir].rotate_right(1);
+    right[2..=nadir].rotate_right(1);
+    right.rotate_right(1);
+}
+
+/// Fills the unknown slots of a partially recovered wheel with the unused
+/// letters in alphabetical order.
+fn fill(wheel: Wheel) -> Vec<usize> {
+    let mut unused = (0..wheel.len()).filter(|x| !wheel.contains(&Some(*x)));
+
+    wheel
+        .iter()
+        .map(|slot| slot.or_else(|| unused.next()).unwrap())
+        .collect()
+}
+
+/// Converts a character to uppercase, if it has a single character uppercase
+/// form.
+fn upper(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: &str = "HXUCZVAMDSLKPEFJRIGTWOBNYQ";
+    const RIGHT: &str = "PTLNBQDEOYSFAVZKGJRIHWXUMC";
+
+    /// The example from Byrne's description of the cipher.
+    #[test]
+    fn byrne_vector() {
+        let cipher = Chaocipher::new(LEFT, RIGHT).unwrap();
+
+        assert_eq!(
+            cipher.encrypt("WELLDONEISBETTERTHANWELLSAID"),
+            "OAHQHCNYNXTSZJRRHJBYHQKSOUJY"
+        );
+        assert_eq!(
+            cipher.decrypt("OAHQHCNYNXTSZJRRHJBYHQKSOUJY"),
+            "WELLDONEISBETTERTHANWELLSAID"
+        );
+    }
+
+    #[test]
+    fn unpermute_at_reverses_permute() {
+        let (left, right) = (
+            LEFT.chars().collect::<Vec<_>>(),
+            RIGHT.chars().collect::<Vec<_>>(),
+        );
+
+        for idx in 0..left.len() {
+            let (mut l, mut r) = (left.clone(), right.clone());
+            permute(&mut l, &mut r, idx);
+            unpermute_at(&mut l, &mut r, idx);
+
+            assert_eq!((l, r), (left.clone(), right.clone()));
+        }
+    }
+
+    /// The search finds the starting wheels for random keys over small
+    /// alphabets, where every letter is constrained by the crib.
+    #[test]
+    fn crib_search_finds_key() {
+        fastrand::seed(1);
+
+        for len in 6..12 {
+            let mut left = (0..len).collect::<Vec<_>>();
+            let mut right = (0..len).collect::<Vec<_>>();
+            fastrand::shuffle(&mut left);
+            fastrand::shuffle(&mut right);
+
+            let pt = (0..40).map(|_| fastrand::usize(..len)).collect::<Vec<_>>();
+            let ct = crypt_indices(left.clone(), right.clone(), &pt);
+
+            let candidates = crib_search(len, &ct, &pt, MAX_NODES).candidates;
+            assert!(candidates
+                .into_iter()
+                .any(|(l, r)| crypt_indices(fill(l), fill(r), &pt) == ct));
+        }
+    }
+
+    /// Encrypts letters given by their indices into the alphabet.
+    fn crypt_indices(mut left: Vec<usize>, mut right: Vec<usize>, pt: &[usize]) -> Vec<usize> {
+        pt.iter()
+            .map(|&p| {
+                let idx = right.iter().position(|&x| x == p).unwrap();
+                let c = left[idx];
+                permute(&mut left, &mut right, idx);
+                c
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,233 @@
+//! Solves ciphertexts which combine a substitution with a transposition.
+//!
+//! A monoalphabetic substitution changes which letters occur but keeps every
+//! letter in place, while a transposition moves letters but keeps their
+//! frequencies. Each layer can therefore be detected, and the transposition
+//! can be searched for with a score which the substitution does not affect.
+
+use crate::{
+    cipher::partial::{self, PartialSolve},
+    cli::crypt::{CliCipher, Pipeline},
+    error::CipherError,
+    restart::Search,
+};
+use classic_crypto::{
+    analysis,
+    cipher::*,
+    lang::{AlphabetLen, Lang},
+};
+use log::debug;
+use std::collections::HashMap;
+
+/// Below this ratio of bigram IoC to squared IoC, adjacent letters are
+/// treated as independent, which is the sign of a transposition.
+const TRANSPOSITION_RATIO: f32 = 1.25;
+/// Above this chi squared value per letter, the letter frequencies are too
+/// far from the language for the text to be unsubstituted.
+const SUBSTITUTION_CHI_SQUARED: f32 = 0.25;
+
+/// Parameters for solving a substitution combined with a transposition.
+pub struct LayeredSolve<'a> {
+    /// The uppercase letters of the lang, which the substitution permutes.
+    pub letters: &'a [char],
+    /// Largest number of rails or faces to try for the transposition.
+    pub max_period: usize,
+    /// Passed to the substitution solver.
+    pub substitution: PartialSolve,
+    /// Passed to the substitution solver.
+    pub search: &'a Search,
+}
+
+/// Which layers appear to be present in a ciphertext.
+#[derive(Debug, Clone, Copy)]
+pub struct Layers {
+    pub substitution: bool,
+    pub transposition: bool,
+}
+
+impl Layers {
+    /// Detects the layers present in `text`. Letters which keep their
+    /// plaintext frequencies indicate that there is no substitution, and
+    /// letters which appear independently of their neighbours indicate a
+    /// transposition.
+    pub fn detect(lang: &Lang, text: &str) -> Self {
+        let alph = lang.with_alphabet(AlphabetLen::Any);
+        let cp = alph.code_points(text).collect::<Vec<_>>();
+        let len = cp.len().max(1) as f32;
+
+        let ioc = analysis::ioc(cp.iter().map(|&x| x as usize));
+        let ratio = bigram_ioc(cp.iter().map(|&x| x as usize)) / (ioc * ioc);
+        let chi_squared = alph.chi_squared(cp) / len;
+
+        debug!("bigram ioc ratio: {ratio}, chi squared per letter: {chi_squared}");
+
+        Self {
+            substitution: chi_squared > SUBSTITUTION_CHI_SQUARED,
+            transposition: ratio < TRANSPOSITION_RATIO,
+        }
+    }
+}
+
+/// Solves a substitution followed by a transposition (or either layer
+/// alone). The transposition is peeled off first, by choosing the Railfence
+/// or Scytale key which maximises the bigram IoC, since that is unaffected by
+/// the substitution. The remaining text is then given to the substitution
+/// solver. Fails if neither layer is detected.
+pub fn solve<'l>(lang: &'l Lang, text: &str, params: LayeredSolve) -> anyhow::Result<Pipeline<'l>> {
+    let LayeredSolve {
+        letters,
+        max_period,
+        substitution,
+        search,
+    } = params;
+
+    let layers = Layers::detect(lang, text);
+    debug!("detected layers: {layers:?}");
+
+    if !layers.substitution && !layers.transposition {
+        return Err(CipherError::NoLayers.into());
+    }
+
+    let mut stages: Vec<Box<dyn CliCipher + 'l>> = Vec::new();
+    let mut text = text.to_string();
+
+    if layers.transposition {
+        let transposition = best_transposition(lang, &text, max_period)?;
+        text = transposition.decrypt(&text);
+        stages.push(transposition);
+    }
+
+    if layers.substitution {
+        let substitution = partial::solve_substitution(lang, letters, &text, substitution, search)?;
+
+        // substitution is applied before transposition when encrypting
+        stages.insert(0, Box::new(substitution));
+    }
+
+    Ok(Pipeline::new(stages))
+}
+
+/// Finds the Railfence or Scytale key whose decryption has the highest
+/// bigram IoC.
+fn best_transposition<'l>(
+    lang: &'l Lang,
+    text: &str,
+    max_period: usize,
+) -> anyhow::Result<Box<dyn CliCipher + 'l>> {
+    let alph = lang.with_alphabet(AlphabetLen::Any);
+    let score = |cipher: &dyn CliCipher| {
+        let plain = cipher.decrypt(text);
+        bigram_ioc(alph.code_points(&plain).map(|x| x as usize))
+    };
+
+    let mut best: Option<(f32, Box<dyn CliCipher + 'l>)> = None;
+
+    for period in 2..=max_period as i32 {
+        let candidates: [Option<Box<dyn CliCipher + 'l>>; 2] = [
+            Railfence::new(lang, period).ok().map(|c| Box::new(c) as _),
+            Scytale::new(lang, period).ok().map(|c| Box::new(c) as _),
+        ];
+
+        for cipher in candidates.into_iter().flatten() {
+            let score = score(cipher.as_ref());
+
+            match &best {
+                Some((best_score, _)) if *best_score >= score => {}
+                _ => best = Some((score, cipher)),
+            }
+        }
+    }
+
+    best.map(|(_, cipher)| cipher)
+        .ok_or_else(|| anyhow::anyhow!("No transposition key could be tried"))
+}
+
+/// Index of coincidence of the (overlapping) bigrams in the text.
+fn bigram_ioc(cp: impl Iterator<Item = usize>) -> f32 {
+    let cp = cp.collect::<Vec<_>>();
+    let mut counts = HashMap::new();
+
+    for pair in cp.windows(2) {
+        *counts.entry((pair[0], pair[1])).or_insert(0usize) += 1;
+    }
+
+    let total = cp.len().saturating_sub(1) as f32;
+    let sum = counts
+        .values()
+        .map(|&n| (n * n.saturating_sub(1)) as f32)
+        .sum::<f32>();
+
+    sum / (total * (total - 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::Stats;
+    use classic_crypto::prelude::StatsSize;
+
+    const CORPUS: &str = include_str!("../../tests/alice/content_only.txt");
+
+    fn lang() -> Lang {
+        Lang::new(
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZ".to_string(),
+            "abcdefghijklmnopqrstuvwxyz".to_string(),
+            CORPUS,
+        )
+        .unwrap()
+    }
+
+    /// The first few paragraphs of the corpus, as uppercase letters.
+    fn plaintext() -> String {
+        CORPUS
+            .chars()
+            .filter(char::is_ascii_alphabetic)
+            .map(|c| c.to_ascii_uppercase())
+            .take(1500)
+            .collect()
+    }
+
+    fn detect(lang: &Lang, text: &str) -> (bool, bool) {
+        let layers = Layers::detect(lang, text);
+        (layers.substitution, layers.transposition)
+    }
+
+    #[test]
+    fn detects_layers() {
+        let lang = lang();
+        let pt = plaintext();
+        let substitution: Box<dyn CliCipher> =
+            Box::new(Substitution::new(&lang, "zebras").unwrap());
+        let scytale: Box<dyn CliCipher> = Box::new(Scytale::new(&lang, 7).unwrap());
+
+        assert_eq!(detect(&lang, &pt), (false, false));
+        assert_eq!(detect(&lang, &substitution.encrypt(&pt)), (true, false));
+        assert_eq!(detect(&lang, &scytale.encrypt(&pt)), (false, true));
+        assert_eq!(
+            detect(&lang, &scytale.encrypt(&substitution.encrypt(&pt))),
+            (true, true)
+        );
+    }
+
+    #[test]
+    fn plaintext_has_no_layers() {
+        let lang = lang();
+        let search = Search::new(None, false);
+        let params = LayeredSolve {
+            letters: &[],
+            max_period: 10,
+            substitution: PartialSolve {
+                stats: Stats::Lang(StatsSize::Quadgrams),
+                max_iterations: 10,
+                min_repetitions: 1,
+            },
+            search: &search,
+        };
+
+        let err = solve(&lang, &plaintext(), params).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CipherError>(),
+            Some(CipherError::NoLayers)
+        ));
+    }
+}
@@ -0,0 +1,9 @@
+//! Ciphers which cannot be expressed with the ciphers provided by
+//! `classic_crypto`.
+
+pub mod chaocipher;
+pub mod layered;
+pub mod partial;
+
+pub use chaocipher::{Chaocipher, ChaocipherSolve};
+pub use layered::LayeredSolve;
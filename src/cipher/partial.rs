@@ -0,0 +1,258 @@
+//! A substitution key which is only partly known. Letters set by hand are
+//! locked, and the solver fills in the rest without changing them.
+
+use crate::{restart::Search, score::Stats};
+use classic_crypto::{cipher::*, lang::Lang, prelude::StatsSize};
+
+/// Parameters for completing a partial key.
+pub struct PartialSolve {
+    /// Statistics used to score candidate keys.
+    pub stats: Stats,
+    /// Upper bound on the number of hill climbing passes, over all restarts.
+    pub max_iterations: usize,
+    /// Number of restarts which must reach the best score before it is
+    /// accepted.
+    pub min_repetitions: usize,
+}
+
+/// A partial substitution key, mapping ciphertext letters to plaintext
+/// letters. Letters are compared in uppercase.
+#[derive(Debug, Clone)]
+pub struct PartialKey {
+    letters: Vec<char>,
+    /// The plaintext letter (as an index into `letters`) for each ciphertext
+    /// letter.
+    plain: Vec<Option<usize>>,
+    /// Whether each ciphertext letter was set by hand.
+    locked: Vec<bool>,
+}
+
+impl PartialKey {
+    /// Creates an empty key over the uppercase `letters`.
+    pub fn new(letters: Vec<char>) -> Self {
+        let len = letters.len();
+
+        Self {
+            letters,
+            plain: vec![None; len],
+            locked: vec![false; len],
+        }
+    }
+
+    /// The uppercase letters of the alphabet.
+    pub fn letters(&self) -> &[char] {
+        &self.letters
+    }
+
+    /// The position of `c` in the alphabet, ignoring case.
+    pub fn index(&self, c: char) -> Option<usize> {
+        let c = c.to_uppercase().next()?;
+        self.letters.iter().position(|&l| l == c)
+    }
+
+    /// The plaintext letter for the ciphertext letter `c`, and whether it
+    /// was set by hand.
+    pub fn get(&self, c: char) -> Option<(char, bool)> {
+        let i = self.index(c)?;
+        self.plain[i].map(|p| (self.letters[p], self.locked[i]))
+    }
+
+    /// Locks the ciphertext letter `cipher` to the plaintext letter `plain`.
+    /// Any other ciphertext letter which decrypted to `plain` is cleared.
+    /// Returns `false` if either letter is not in the alphabet.
+    pub fn set(&mut self, cipher: char, plain: char) -> bool {
+        let (c, p) = match (self.index(cipher), self.index(plain)) {
+            (Some(c), Some(p)) => (c, p),
+            _ => return false,
+        };
+
+        for i in 0..self.plain.len() {
+            if self.plain[i] == Some(p) {
+                self.plain[i] = None;
+                self.locked[i] = false;
+            }
+        }
+
+        self.plain[c] = Some(p);
+        self.locked[c] = true;
+        true
+    }
+
+    /// Clears the ciphertext letter `cipher`.
+    pub fn clear(&mut self, cipher: char) {
+        if let Some(c) = self.index(cipher) {
+            self.plain[c] = None;
+            self.locked[c] = false;
+        }
+    }
+
+    /// Decrypts `text`, writing `unknown` for letters which have no
+    /// plaintext letter yet. The case of each letter is kept.
+    pub fn decrypt(&self, text: &str, unknown: char) -> String {
+        text.chars()
+            .map(|c| match self.index(c) {
+                Some(i) => match self.plain[i] {
+                    Some(p) if c.is_lowercase() => {
+                        self.letters[p].to_lowercase().next().unwrap_or(unknown)
+                    }
+                    Some(p) => self.letters[p],
+                    None => unknown,
+                },
+                None => c,
+            })
+            .collect()
+    }
+
+    /// The ciphertext letter for each plaintext letter, in alphabet order,
+    /// with `.` for plaintext letters which are not known.
+    pub fn key(&self) -> String {
+        (0..self.letters.len())
+            .map(|p| match self.plain.iter().position(|&x| x == Some(p)) {
+                Some(c) => self.letters[c],
+                None => '.',
+            })
+            .collect()
+    }
+
+    /// Fills in every letter which is not locked, by hill climbing from
+    /// random keys and keeping the key whose decryption of `text` scores
+    /// highest. Stops early, with the best key so far, if `search` expires.
+    pub fn solve(&mut self, lang: &Lang, text: &str, params: PartialSolve, search: &Search) {
+        let PartialSolve {
+            stats,
+            max_iterations,
+            min_repetitions,
+        } = params;
+
+        let cipher = text
+            .chars()
+            .filter_map(|c| self.index(c))
+            .collect::<Vec<_>>();
+        let free_cipher = (0..self.letters.len())
+            .filter(|&c| !self.locked[c])
+            .collect::<Vec<_>>();
+        let free_plain = (0..self.letters.len())
+            .filter(|&p| {
+                !(0..self.letters.len()).any(|c| self.locked[c] && self.plain[c] == Some(p))
+            })
+            .collect::<Vec<_>>();
+
+        if free_cipher.is_empty() {
+            return;
+        }
+
+        let score = |key: &[usize]| {
+            let plain = cipher
+                .iter()
+                .map(|&c| self.letters[key[c]])
+                .collect::<String>();
+            stats.score(lang, &plain)
+        };
+        let decrypt = |key: &[usize]| {
+            text.chars()
+                .map(|c| match self.index(c) {
+                    Some(i) => self.letters[key[i]],
+                    None => c,
+                })
+                .collect::<String>()
+        };
+
+        let base = self
+            .plain
+            .iter()
+            .zip(&self.locked)
+            .map(|(&p, &locked)| match locked {
+                true => p.unwrap_or_default(),
+                false => 0,
+            })
+            .collect::<Vec<_>>();
+
+        let mut best: Option<(f32, Vec<usize>)> = None;
+        let mut repetitions = 0;
+        let mut iterations = 0;
+
+        // the first climb always runs, so that every letter is filled in
+        while iterations < max_iterations
+            && repetitions < min_repetitions
+            && (best.is_none() || !search.expired())
+        {
+            let mut shuffled = free_plain.clone();
+            fastrand::shuffle(&mut shuffled);
+
+            let mut key = base.clone();
+            for (&c, &p) in free_cipher.iter().zip(&shuffled) {
+                key[c] = p;
+            }
+
+            let mut key_score = score(&key);
+            let mut improved = true;
+
+            while improved && iterations < max_iterations && !search.expired() {
+                improved = false;
+                iterations += 1;
+                search.iteration();
+
+                for a in 0..free_cipher.len() {
+                    for b in a + 1..free_cipher.len() {
+                        key.swap(free_cipher[a], free_cipher[b]);
+                        let swapped_score = score(&key);
+
+                        if swapped_score > key_score {
+                            key_score = swapped_score;
+                            improved = true;
+                        } else {
+                            key.swap(free_cipher[a], free_cipher[b]);
+                        }
+                    }
+                }
+            }
+
+            match &best {
+                Some((best_score, _)) if (*best_score - key_score).abs() < f32::EPSILON => {
+                    repetitions += 1
+                }
+                Some((best_score, _)) if *best_score > key_score => {}
+                _ => {
+                    search.result(key_score, || decrypt(&key));
+                    best = Some((key_score, key));
+                    repetitions = 1;
+                }
+            }
+        }
+
+        if let Some((_, key)) = best {
+            for &c in &free_cipher {
+                self.plain[c] = Some(key[c]);
+            }
+        }
+    }
+}
+
+/// Solves `text` as a substitution over the uppercase `letters`, by filling
+/// in an empty key. The solver from `classic_crypto` is used instead for the
+/// lang's n-grams, and for langs which don't record their letters, which are
+/// then scored with quadgrams.
+pub fn solve_substitution<'l>(
+    lang: &'l Lang,
+    letters: &[char],
+    text: &str,
+    params: PartialSolve,
+    search: &Search,
+) -> anyhow::Result<Substitution<'l>> {
+    if params.stats.size().is_some() || letters.is_empty() {
+        return Ok(Substitution::solve(
+            lang,
+            text,
+            SubstitutionSolve {
+                stats_size: params.stats.size().unwrap_or(StatsSize::Quadgrams),
+                max_iterations: params.max_iterations,
+                min_repetitions: params.min_repetitions,
+            },
+        ));
+    }
+
+    let mut key = PartialKey::new(letters.to_vec());
+    key.solve(lang, text, params, search);
+
+    Ok(Substitution::new(lang, &key.key())?)
+}
@@ -0,0 +1,313 @@
+//! Cleans raw text, such as Project Gutenberg ebooks, before it is used as a
+//! corpus.
+
+use crate::{alphabet::Alphabet, cli::CleanOpt};
+use regex::Regex;
+
+/// Accented letters, by the letter they are folded to.
+const ACCENTS: [(char, &str); 14] = [
+    ('a', "àáâãäåāăąǎ"),
+    ('c', "çćĉċč"),
+    ('d', "ďđ"),
+    ('e', "èéêëēĕėęě"),
+    ('g', "ĝğġģ"),
+    ('i', "ìíîïĩīĭįı"),
+    ('l', "ĺļľŀł"),
+    ('n', "ñńņňŉ"),
+    ('o', "òóôõöøōŏő"),
+    ('r', "ŕŗř"),
+    ('s', "śŝşšș"),
+    ('t', "ţťŧț"),
+    ('u', "ùúûüũūŭůűų"),
+    ('y', "ýÿŷ"),
+];
+
+/// Letters folded to more than one letter.
+const LIGATURES: [(char, &str); 4] = [('ß', "ss"), ('æ', "ae"), ('œ', "oe"), ('þ', "th")];
+
+/// Lines read before giving up on finding the start of a Project Gutenberg
+/// ebook, or its first chapter, and treating them as text.
+const HEADER_LINES: usize = 1000;
+
+/// Openings of the notes which transcribers add to Project Gutenberg ebooks,
+/// which end at the next closing bracket.
+const NOTES: [&str; 3] = ["[Illustration", "[Sidenote", "[Footnote"];
+
+/// A roman numeral, which may be empty.
+const ROMAN: &str = "m{0,3}(?:cm|cd|d?c{0,3})(?:xc|xl|l?x{0,3})(?:ix|iv|v?i{0,3})";
+
+/// Where a line is within a Project Gutenberg ebook.
+enum Gutenberg {
+    /// Before the start marker. The lines are kept in case there is none.
+    Header(Vec<String>),
+    /// After the start marker, before any production notes.
+    Start,
+    /// Within the production notes, which are the first paragraph.
+    Notes,
+    /// Before the first chapter heading, such as the title page and
+    /// contents. The lines are kept in case there is no heading.
+    FrontMatter(Vec<String>),
+    Content,
+    /// From the end marker, the line which may come before it, or "THE END".
+    Footer,
+}
+
+/// Applies the cleaning options to text a line at a time, so that large
+/// corpora need not be read into memory. Letters are only folded if they
+/// are not in `alphabet`, or if it accepts any letter.
+pub struct Cleaner<'a> {
+    opt: &'a CleanOpt,
+    alphabet: &'a Alphabet,
+    heading: Regex,
+    gutenberg: Gutenberg,
+    /// Whether the previous line was within a note which is not closed yet.
+    note: bool,
+}
+
+impl<'a> Cleaner<'a> {
+    pub fn new(opt: &'a CleanOpt, alphabet: &'a Alphabet) -> Self {
+        let heading = Regex::new(&format!(
+            r"(?i)^\s*((chapter|book|part|volume|section)\s+([0-9]+|{ROMAN})([.:](\s.*)?)?|[0-9]+\.?|{ROMAN}\.|\*(\s*\*)*)\s*$",
+        ))
+        .expect("heading pattern is valid");
+
+        Self {
+            opt,
+            alphabet,
+            heading,
+            gutenberg: match opt.strip_gutenberg || opt.clean {
+                true => Gutenberg::Header(Vec::new()),
+                false => Gutenberg::Content,
+            },
+            note: false,
+        }
+    }
+
+    /// Cleans `line`, adding what should be kept to `out`.
+    pub fn line(&mut self, line: &str, out: &mut Vec<String>) {
+        match &mut self.gutenberg {
+            Gutenberg::Header(_) if is_marker(line, "START OF") => {
+                self.gutenberg = Gutenberg::Start;
+            }
+            Gutenberg::Header(lines) if lines.len() < HEADER_LINES => {
+                lines.push(line.to_string());
+            }
+            Gutenberg::Header(_) => {
+                self.finish(out);
+                self.content(line, out);
+            }
+            Gutenberg::Start if line.trim().is_empty() => {}
+            Gutenberg::Start if line.starts_with("Produced by") => {
+                self.gutenberg = Gutenberg::Notes;
+            }
+            Gutenberg::Notes if line.trim().is_empty() => {
+                self.gutenberg = Gutenberg::FrontMatter(Vec::new());
+            }
+            Gutenberg::Notes | Gutenberg::Footer => {}
+            Gutenberg::Start | Gutenberg::FrontMatter(_) if self.heading.is_match(line) => {
+                self.gutenberg = Gutenberg::Content;
+                self.content(line, out);
+            }
+            Gutenberg::Start => self.gutenberg = Gutenberg::FrontMatter(vec![line.to_string()]),
+            Gutenberg::FrontMatter(lines) if lines.len() < HEADER_LINES => {
+                lines.push(line.to_string());
+            }
+            Gutenberg::FrontMatter(_) => {
+                self.finish(out);
+                self.content(line, out);
+            }
+            Gutenberg::Content => self.content(line, out),
+        }
+    }
+
+    /// Adds any lines which are still held to `out`, once the text has been
+    /// read.
+    pub fn finish(&mut self, out: &mut Vec<String>) {
+        if let Gutenberg::Header(lines) | Gutenberg::FrontMatter(lines) = &mut self.gutenberg {
+            let lines = std::mem::take(lines);
+            self.gutenberg = Gutenberg::Content;
+
+            for line in lines {
+                self.content(&line, out);
+            }
+        }
+    }
+
+    fn content(&mut self, line: &str, out: &mut Vec<String>) {
+        let footer = is_marker(line, "END OF")
+            || (line.starts_with("End of") && line.contains("Project Gutenberg"))
+            || line.trim() == "THE END";
+
+        if self.opt.strip_gutenberg || self.opt.clean {
+            if footer {
+                self.gutenberg = Gutenberg::Footer;
+                return;
+            }
+
+            let trimmed = line.trim_start();
+            if self.note || NOTES.iter().any(|note| trimmed.starts_with(note)) {
+                self.note = !line.contains(']');
+                return;
+            }
+        }
+
+        let mut line = match self.opt.normalise || self.opt.clean {
+            true => normalise(line),
+            false => line.to_string(),
+        };
+
+        if (self.opt.strip_headings || self.opt.clean) && self.heading.is_match(&line) {
+            return;
+        }
+
+        if self.opt.fold_accents || self.opt.clean {
+            line = fold_accents(&line, self.alphabet);
+        }
+
+        out.push(line);
+    }
+}
+
+/// Whether `line` is one of the markers around the text of a Project
+/// Gutenberg ebook.
+fn is_marker(line: &str, marker: &str) -> bool {
+    let line = line.to_uppercase();
+    line.starts_with("***") && line.contains(marker) && line.contains("GUTENBERG")
+}
+
+/// Replaces typographic quotes, dashes and spaces with their plain forms.
+fn normalise(text: &str) -> String {
+    let mut normalised = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' | '\u{2032}' => normalised.push('\''),
+            '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{201f}' | '\u{2033}' | '«' | '»' => {
+                normalised.push('"')
+            }
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => normalised.push('-'),
+            '\u{2026}' => normalised.push_str("..."),
+            '\u{a0}' | '\u{2000}'..='\u{200a}' | '\u{202f}' => normalised.push(' '),
+            '\u{200b}' | '\u{feff}' | '\u{ad}' => {}
+            c => normalised.push(c),
+        }
+    }
+
+    normalised
+}
+
+/// Replaces accented letters which are not in `alphabet` with the letters
+/// they are formed from, if those are in it. If `alphabet` accepts any
+/// letter then every accented letter is replaced.
+fn fold_accents(text: &str, alphabet: &Alphabet) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if (alphabet.is_letter(c) && !alphabet.is_any()) || !c.is_alphabetic() {
+            folded.push(c);
+            continue;
+        }
+
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        let base = ACCENTS
+            .iter()
+            .find(|(_, accented)| accented.contains(lower))
+            .map(|&(base, _)| base.to_string())
+            .or_else(|| {
+                LIGATURES
+                    .iter()
+                    .find(|&&(ligature, _)| ligature == lower)
+                    .map(|&(_, base)| base.to_string())
+            })
+            .filter(|base| base.chars().all(|b| alphabet.is_letter(b)));
+
+        match base {
+            Some(base) if c.is_uppercase() => folded.push_str(&base.to_uppercase()),
+            Some(base) => folded.push_str(&base),
+            None => folded.push(c),
+        }
+    }
+
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_letters_outside_the_alphabet() {
+        let alphabet = Alphabet::new("ABCDEFGHIJKLMNOPQRSTUVWXYZÉ", "");
+
+        assert_eq!(fold_accents("Café Noël Æsop", &alphabet), "Café Noel AEsop");
+    }
+
+    #[test]
+    fn folds_every_letter_without_an_alphabet() {
+        assert_eq!(
+            fold_accents("Café Noël Æsop", &Alphabet::default()),
+            "Cafe Noel AEsop"
+        );
+    }
+
+    #[test]
+    fn strips_roman_numeral_headings_only() {
+        let opt = CleanOpt {
+            clean: false,
+            strip_gutenberg: false,
+            normalise: false,
+            strip_headings: true,
+            fold_accents: false,
+        };
+        let alphabet = Alphabet::default();
+        let mut cleaner = Cleaner::new(&opt, &alphabet);
+        let mut out = Vec::new();
+
+        for line in [
+            "XIV.",
+            "Chapter ix",
+            "12",
+            "*   *   *",
+            "did.",
+            "mild.",
+            "Civic.",
+            "I.",
+        ] {
+            cleaner.line(line, &mut out);
+        }
+
+        assert_eq!(out, ["did.", "mild.", "Civic."]);
+    }
+
+    /// The lines which are not blank, since blank lines around what is
+    /// removed are kept.
+    fn text_lines(text: &str) -> Vec<&str> {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn cleans_gutenberg_ebook() {
+        let opt = CleanOpt {
+            clean: false,
+            strip_gutenberg: true,
+            normalise: true,
+            strip_headings: true,
+            fold_accents: false,
+        };
+        let alphabet = Alphabet::default();
+        let mut cleaner = Cleaner::new(&opt, &alphabet);
+        let mut out = Vec::new();
+
+        for line in include_str!("../tests/alice/corpus.txt").lines() {
+            cleaner.line(line, &mut out);
+        }
+        cleaner.finish(&mut out);
+
+        assert_eq!(
+            text_lines(&out.join("\n")),
+            text_lines(include_str!("../tests/alice/content_only.txt"))
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Solves many ciphertexts at once, in parallel.
+
+use crate::{
+    cli::{
+        opt::CipherSolveCmd,
+        solve::{self, SolveContext},
+        StatsSizeOpt,
+    },
+    restart::Search,
+    util, CipherConfig,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Write, path::PathBuf, time::Instant};
+
+/// A ciphertext read from a JSON lines file.
+#[derive(Deserialize, Debug)]
+struct BatchInput {
+    id: Option<String>,
+    text: String,
+}
+
+/// The outcome of solving one ciphertext, written as one line of JSON.
+#[derive(Serialize, Debug)]
+struct BatchResult {
+    id: String,
+    key: Option<String>,
+    score: Option<f32>,
+    plaintext: Option<String>,
+    error: Option<String>,
+    millis: u128,
+}
+
+pub fn batch(
+    cfg: &CipherConfig,
+    cipher: Option<CipherSolveCmd>,
+    input: PathBuf,
+    output: Option<PathBuf>,
+    jobs: Option<usize>,
+    stats_size: Option<StatsSizeOpt>,
+    lang: Option<String>,
+) -> anyhow::Result<()> {
+    let stats = &solve::stats_or_default(cfg, lang.clone(), stats_size, None)?;
+    let letters = &cfg.letters_or_selected(lang.clone()).unwrap_or_default();
+    let lang = &cfg.load_lang_or_selected(lang)?;
+    let inputs = read_batch(input)?;
+    let mut out = util::output(output.as_deref())?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()?;
+
+    let results = pool.install(|| {
+        inputs
+            .par_iter()
+            .map(|input| {
+                let start = Instant::now();
+                let ctx = SolveContext {
+                    lang,
+                    letters,
+                    stats,
+                    search: &Search::new(None, false),
+                };
+                let solution = solve::solve_text(&ctx, &input.text, cipher.as_ref(), None, None);
+                let id = input.id.clone().unwrap_or_default();
+                let millis = start.elapsed().as_millis();
+
+                match solution {
+                    Ok(solution) => BatchResult {
+                        id,
+                        key: Some(solution.key),
+                        score: Some(solution.score),
+                        plaintext: Some(solution.plaintext),
+                        error: None,
+                        millis,
+                    },
+                    Err(e) => BatchResult {
+                        id,
+                        key: None,
+                        score: None,
+                        plaintext: None,
+                        error: Some(e.to_string()),
+                        millis,
+                    },
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+
+    for result in results {
+        writeln!(out, "{}", serde_json::to_string(&result)?)?;
+    }
+
+    Ok(())
+}
+
+/// Reads ciphertexts from each file in a directory (in name order, using
+/// the file name as the id), or from a JSON lines file (using the line
+/// number as the id where none is given).
+fn read_batch(input: PathBuf) -> anyhow::Result<Vec<BatchInput>> {
+    if input.is_dir() {
+        let mut paths = fs::read_dir(input)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                Ok(BatchInput {
+                    id: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned()),
+                    text: fs::read_to_string(&path)?,
+                })
+            })
+            .collect()
+    } else {
+        fs::read_to_string(input)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let mut input: BatchInput = serde_json::from_str(line)?;
+                input.id.get_or_insert_with(|| (i + 1).to_string());
+                Ok(input)
+            })
+            .collect()
+    }
+}
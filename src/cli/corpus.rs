@@ -1,63 +1,593 @@
-use crate::util::stdin_to_string;
+use crate::{
+    alphabet::Alphabet,
+    clean::Cleaner,
+    cli::{
+        segment::{WordBigrams, Words},
+        CleanOpt, Source,
+    },
+    error::CipherError,
+    score::Quintgrams,
+    CipherConfig,
+};
+use classic_crypto::lang::Lang;
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::hash_map::{Entry, HashMap},
+    collections::HashMap,
     fs,
-    io::{self, Write},
-    path::PathBuf,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
 };
 
-// find all words
-pub fn words(s: &str) -> Vec<String> {
-    let r_find = regex::Regex::new(r"\b[a-zA-Z]+('[a-zA-Z'])?\b").unwrap();
-    r_find
-        .find_iter(s)
-        .map(|m| m.as_str().to_lowercase())
-        .collect()
+// find all words, in lowercase. Apostrophes are kept between letters
+pub fn words(s: &str, alphabet: &Alphabet) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match alphabet.to_lower(c) {
+            Some(lower) => word.push(lower),
+            None if c == '\''
+                && !word.is_empty()
+                && chars.peek().map(|&c| alphabet.is_letter(c)) == Some(true) =>
+            {
+                word.push(c)
+            }
+            None if !word.is_empty() => words.push(std::mem::take(&mut word)),
+            None => {}
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
 }
 
-// find word frequencies
-pub fn frequencies(words: impl IntoIterator<Item = String>) -> HashMap<String, usize> {
+// read a frequency file, with a word and its count on each line
+pub fn read_frequencies(s: &str, alphabet: &Alphabet) -> anyhow::Result<HashMap<String, usize>> {
     let mut freqs = HashMap::new();
-    for w in words {
-        match freqs.entry(w) {
-            Entry::Occupied(mut e) => {
-                *e.get_mut() += 1;
+
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+        let (word, count) = line
+            .trim()
+            .rsplit_once(char::is_whitespace)
+            .ok_or(CipherError::InvalidFrequencies)?;
+        let count = count
+            .parse::<usize>()
+            .map_err(|_| CipherError::InvalidFrequencies)?;
+
+        for w in words(word, alphabet) {
+            *freqs.entry(w).or_insert(0) += count;
+        }
+    }
+
+    Ok(freqs)
+}
+
+// read a dictionary, with one word on each line, giving each word `count`
+pub fn read_dictionary(s: &str, alphabet: &Alphabet, count: usize) -> HashMap<String, usize> {
+    s.lines()
+        .flat_map(|line| words(line, alphabet))
+        .map(|w| (w, count))
+        .collect()
+}
+
+/// Names of the files of letter n-gram counts, by n.
+const NGRAM_FILES: [&str; MAX_NGRAM] = [
+    "unigrams.txt",
+    "bigrams.txt",
+    "trigrams.txt",
+    "quadgrams.txt",
+    "quintgrams.txt",
+];
+
+/// Longest letter n-gram counted.
+const MAX_NGRAM: usize = 5;
+
+/// Bits used by each letter in a packed n-gram.
+const CHAR_BITS: usize = 21;
+
+/// Most letters of text generated to build the lang from. The n-gram counts
+/// of larger corpora are scaled down to fit.
+const LANG_LETTERS: usize = 16 * 1024 * 1024;
+
+/// Counts of the words and letters of a corpus, which is read a line at a
+/// time. These are stored with a lang so that more corpus can be added to
+/// it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Counts {
+    /// Stored in the word list rather than with the other counts.
+    #[serde(skip)]
+    pub words: HashMap<String, usize>,
+    pub word_bigrams: HashMap<(String, String), usize>,
+    /// Packed letter n-grams in lowercase, for each n from 1.
+    ngrams: [HashMap<u128, usize>; MAX_NGRAM],
+    #[serde(skip)]
+    prev_word: Option<String>,
+    /// The last letters read, which begin the n-grams of the next line.
+    #[serde(skip)]
+    tail: Vec<char>,
+}
+
+impl Counts {
+    /// Counts the words and letters of `line` `weight` times, returning its
+    /// words.
+    fn add_line(&mut self, line: &str, alphabet: &Alphabet, weight: usize) -> Vec<String> {
+        let words = words(line, alphabet);
+
+        for w in &words {
+            *self.words.entry(w.clone()).or_insert(0) += weight;
+
+            if let Some(prev) = self.prev_word.replace(w.clone()) {
+                *self.word_bigrams.entry((prev, w.clone())).or_insert(0) += weight;
             }
-            Entry::Vacant(e) => {
-                e.insert(1);
+        }
+
+        let mut letters = std::mem::take(&mut self.tail);
+        let start = letters.len();
+        letters.extend(line.chars().filter_map(|c| alphabet.to_lower(c)));
+
+        for end in start..letters.len() {
+            for n in 1..=MAX_NGRAM.min(end + 1) {
+                let ngram = pack(&letters[end + 1 - n..=end]);
+                *self.ngrams[n - 1].entry(ngram).or_insert(0) += weight;
             }
         }
+
+        letters.drain(..letters.len().saturating_sub(MAX_NGRAM - 1));
+        self.tail = letters;
+
+        words
     }
 
-    freqs
+    /// Words and n-grams are not counted across the end of a source.
+    fn end_source(&mut self) {
+        self.prev_word = None;
+        self.tail.clear();
+    }
+
+    /// The counts of the letter n-grams of length `n`.
+    pub fn ngrams(&self, n: usize) -> HashMap<String, usize> {
+        self.ngrams[n - 1]
+            .iter()
+            .map(|(&ngram, &count)| (unpack(ngram, n), count))
+            .collect()
+    }
+
+    /// Text whose quadgrams are those counted, in the same proportions, for
+    /// `Lang`, which can only count its statistics from text. Each quadgram
+    /// leads from its first three letters to its last three, and the text
+    /// follows these steps as many times as each was counted, scaled down to
+    /// at most `LANG_LETTERS`. Where no step is left, the text starts a new
+    /// line from letters which still have steps. Corpora too short for
+    /// quadgrams use the longest n-grams they have.
+    fn lang_text(&self) -> String {
+        let n = match (1..MAX_NGRAM)
+            .rev()
+            .find(|&n| !self.ngrams[n - 1].is_empty())
+        {
+            Some(n) => n,
+            None => return String::new(),
+        };
+        let total = self.ngrams[n - 1].values().sum::<usize>();
+        let scale = (LANG_LETTERS as f64 / total as f64).min(1.0);
+        let node_mask = (1 << ((n - 1) * CHAR_BITS)) - 1;
+
+        // the first and last n - 1 letters of each n-gram, numbered in order
+        let mut nodes = self.ngrams[n - 1]
+            .keys()
+            .flat_map(|&ngram| [ngram >> CHAR_BITS, ngram & node_mask])
+            .collect::<Vec<_>>();
+        nodes.sort_unstable();
+        nodes.dedup();
+        let id = |node| nodes.binary_search(&node).expect("every node is numbered");
+
+        // the steps from each node, with the node they reach and how many
+        // times each is left to take, and how many more steps leave each
+        // node than arrive at it
+        let mut steps = vec![Vec::new(); nodes.len()];
+        let mut surplus = vec![0isize; nodes.len()];
+
+        for (&ngram, &count) in &self.ngrams[n - 1] {
+            let count = ((count as f64 * scale).round() as usize).max(1);
+            let (from, to) = (id(ngram >> CHAR_BITS), id(ngram & node_mask));
+
+            steps[from].push((ngram, to, count));
+            surplus[from] += count as isize;
+            surplus[to] -= count as isize;
+        }
+        for out in &mut steps {
+            out.sort_unstable();
+        }
+
+        // a path through every step starts where more steps leave than
+        // arrive, so start there to need as few lines as possible
+        let mut starts = (0..nodes.len()).collect::<Vec<_>>();
+        starts.sort_by_key(|&node| surplus[node] <= 0);
+
+        let mut text = String::new();
+        for start in starts {
+            while !steps[start].is_empty() {
+                text.push_str(&unpack(nodes[start], n - 1));
+                let mut node = start;
+
+                while let Some(step) = steps[node].last_mut() {
+                    step.2 -= 1;
+                    let (ngram, next, left) = *step;
+                    if left == 0 {
+                        steps[node].pop();
+                    }
+
+                    text.extend(char::from_u32((ngram & ((1 << CHAR_BITS) - 1)) as u32));
+                    node = next;
+                }
+
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+
+    /// Builds the statistics which are stored with a lang, all from the
+    /// counts.
+    pub fn build(&self, upper: String, lower: String) -> anyhow::Result<(Lang, Words, Quintgrams)> {
+        let alphabet = Alphabet::new(&upper, &lower);
+        let lang = Lang::new(upper, lower, &self.lang_text())?;
+        let quintgrams = Quintgrams::from_counts(&lang, &self.ngrams(MAX_NGRAM));
+
+        let mut words = Words::from_counts(self.words.clone(), &alphabet);
+        words.set_bigrams(WordBigrams::from_counts(&self.word_bigrams));
+
+        Ok((lang, words, quintgrams))
+    }
+}
+
+/// Packs the letters of an n-gram into a single key.
+fn pack(ngram: &[char]) -> u128 {
+    ngram
+        .iter()
+        .fold(0, |key, &c| (key << CHAR_BITS) | c as u128)
+}
+
+fn unpack(key: u128, n: usize) -> String {
+    (0..n)
+        .rev()
+        .filter_map(|i| char::from_u32((key >> (i * CHAR_BITS)) as u32 & ((1 << CHAR_BITS) - 1)))
+        .collect()
 }
 
-pub fn corpus(file: Option<PathBuf>, out: PathBuf) -> anyhow::Result<()> {
-    let corpus = match file {
-        Some(path) => std::fs::read_to_string(path)?,
-        None => stdin_to_string()?,
+/// Reads and adds to `counts` `text`, or if it is not present each of
+/// `sources`, or stdin if there are none, a line at a time. The words of each
+/// line are given to `on_words`.
+pub fn read_sources(
+    mut counts: Counts,
+    sources: &[Source],
+    text: Option<&str>,
+    alphabet: &Alphabet,
+    clean: &CleanOpt,
+    mut on_words: impl FnMut(&[String]) -> io::Result<()>,
+) -> anyhow::Result<Counts> {
+    let mut read = |reader: &mut dyn BufRead, weight| {
+        read_source(reader, weight, &mut counts, alphabet, clean, &mut on_words)
     };
 
-    let words = words(&corpus);
+    match text {
+        Some(text) => read(&mut text.as_bytes(), 1)?,
+        None if sources.is_empty() => read(&mut io::stdin().lock(), 1)?,
+        None => {
+            for source in sources {
+                for path in files(&source.path)? {
+                    read(&mut *open(&path)?, source.weight)?;
+                }
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+fn read_source(
+    reader: &mut dyn BufRead,
+    weight: usize,
+    counts: &mut Counts,
+    alphabet: &Alphabet,
+    clean: &CleanOpt,
+    on_words: &mut impl FnMut(&[String]) -> io::Result<()>,
+) -> anyhow::Result<()> {
+    let mut cleaner = Cleaner::new(clean, alphabet);
+    let mut buf = Vec::new();
+    let mut lines = Vec::new();
+
+    loop {
+        buf.clear();
+        let done = reader.read_until(b'\n', &mut buf)? == 0;
+
+        match done {
+            true => cleaner.finish(&mut lines),
+            // corpora are not always valid UTF-8
+            false => cleaner.line(String::from_utf8_lossy(&buf).trim_end(), &mut lines),
+        }
 
-    // write word file
-    let f_words_path = out.join("words.txt");
-    println!("writing {}", f_words_path.to_string_lossy());
-    let mut f_words = io::BufWriter::new(fs::File::create(f_words_path)?);
-    for w in &words {
-        write!(f_words, "{w} ")?;
+        for line in lines.drain(..) {
+            on_words(&counts.add_line(&line, alphabet, weight))?;
+        }
+
+        if done {
+            break;
+        }
     }
 
-    let mut freqs = frequencies(words).into_iter().collect::<Vec<_>>();
-    freqs.sort_unstable_by_key(|&(_, f)| usize::MAX - f);
+    counts.end_source();
+
+    Ok(())
+}
+
+/// The files at `path`, which are all of the files within it, in order, if
+/// it is a directory.
+fn files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+    for entry in entries {
+        files.extend(self::files(&entry)?);
+    }
+
+    Ok(files)
+}
+
+/// Opens the file at `path`, decompressing it if it ends in `.gz`.
+fn open(path: &Path) -> anyhow::Result<Box<dyn BufRead>> {
+    let file = fs::File::open(path)?;
+
+    Ok(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(io::BufReader::new(MultiGzDecoder::new(file))),
+        _ => Box::new(io::BufReader::new(file)),
+    })
+}
+
+/// Sorts `freqs` with the most frequent first, then by key.
+fn sorted<T: Ord>(freqs: HashMap<T, usize>) -> Vec<(T, usize)> {
+    let mut freqs = freqs.into_iter().collect::<Vec<_>>();
+    freqs.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    freqs
+}
+
+/// Creates the file `name` in `out`.
+fn create(out: &Path, name: &str) -> anyhow::Result<io::BufWriter<fs::File>> {
+    let path = out.join(name);
+    println!("writing {}", path.to_string_lossy());
+    Ok(io::BufWriter::new(fs::File::create(path)?))
+}
+
+/// Writes the number of letters counted, the number of distinct letters, and
+/// the expected IoC and entropy of the letters.
+fn write_summary(out: &mut impl Write, counts: &Counts) -> io::Result<()> {
+    let unigrams = counts.ngrams(1);
+    let total = unigrams.values().sum::<usize>() as f64;
+    let expected_ioc = unigrams
+        .values()
+        .map(|&n| (n * n.saturating_sub(1)) as f64)
+        .sum::<f64>()
+        / (total * (total - 1.0)).max(1.0);
+    let entropy = -unigrams
+        .values()
+        .map(|&n| n as f64 / total)
+        .map(|p| p * p.log2())
+        .sum::<f64>();
+
+    writeln!(out, "letters\t{total}")?;
+    writeln!(out, "distinct letters\t{}", unigrams.len())?;
+    writeln!(out, "expected ioc\t{expected_ioc:.6}")?;
+    writeln!(out, "entropy\t{entropy:.6}")
+}
+
+pub fn corpus(
+    cfg: &CipherConfig,
+    files: Vec<Source>,
+    out: PathBuf,
+    lang: Option<String>,
+    letters: Option<(String, String)>,
+    clean: CleanOpt,
+) -> anyhow::Result<()> {
+    // the corpus may be processed before its lang has been added
+    let alphabet = match &letters {
+        Some((upper, lower)) => Alphabet::new(upper, lower),
+        None => cfg.alphabet_or_selected(lang.clone()).unwrap_or_default(),
+    };
+
+    // write word file as the corpus is read
+    let mut f_words = create(&out, "words.txt")?;
+    let counts = read_sources(
+        Counts::default(),
+        &files,
+        None,
+        &alphabet,
+        &clean,
+        |words| words.iter().try_for_each(|w| write!(f_words, "{w} ")),
+    )?;
+    f_words.flush()?;
+
+    // write word bigram file
+    let mut f_bigrams = create(&out, "word_bigrams.txt")?;
+    for ((prev, w), freq) in sorted(counts.word_bigrams.clone()) {
+        writeln!(f_bigrams, "{prev} {w}\t{freq}")?;
+    }
 
     // write frequency file
-    let f_frequency_path = out.join("freqs.txt");
-    println!("writing {}", f_frequency_path.to_string_lossy());
-    let mut f_frequency = io::BufWriter::new(fs::File::create(f_frequency_path)?);
-    for (w, freq) in freqs {
+    let mut f_frequency = create(&out, "freqs.txt")?;
+    for (w, freq) in sorted(counts.words.clone()) {
         writeln!(f_frequency, "{w}\t{freq}")?;
     }
 
+    // write letter n-gram files, with letters in lowercase
+    for (i, name) in NGRAM_FILES.iter().enumerate() {
+        let mut f_ngrams = create(&out, name)?;
+        for (ngram, freq) in sorted(counts.ngrams(i + 1)) {
+            writeln!(f_ngrams, "{ngram}\t{freq}")?;
+        }
+    }
+
+    // write summary statistics
+    write_summary(&mut create(&out, "summary.txt")?, &counts)?;
+
+    // write the statistics in the form they are loaded with a lang
+    let (upper, lower) = match letters.or_else(|| {
+        cfg.alphabet_or_selected(lang)
+            .ok()
+            .map(|alphabet| alphabet.letters())
+    }) {
+        Some(letters) => letters,
+        None => {
+            println!("no alphabet was given, so the binary statistics were not written");
+            return Ok(());
+        }
+    };
+
+    let (lang, words, quintgrams) = counts.build(upper, lower)?;
+
+    create(&out, "corpus.lang")?.write_all(&bincode::serialize(&lang)?)?;
+    create(&out, "corpus.quint")?.write_all(&bincode::serialize(&quintgrams)?)?;
+    create(&out, "corpus.words")?.write_all(&bincode::serialize(&words)?)?;
+    create(&out, "corpus.wbigrams")?.write_all(&bincode::serialize(words.bigrams())?)?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::segment;
+    use classic_crypto::lang::AlphabetLen;
+
+    const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+    /// Serialises and deserialises `value`, as it is when stored with a lang.
+    fn reload<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> T {
+        bincode::deserialize(&bincode::serialize(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn counts_survive_a_round_trip() {
+        let alphabet = Alphabet::new(UPPER, "");
+        let clean = CleanOpt {
+            clean: false,
+            strip_gutenberg: false,
+            normalise: false,
+            strip_headings: false,
+            fold_accents: false,
+        };
+        // "cat" ends the second line and "sat" begins the third
+        let text = "The cat sat.\nThe cat\nsat on the mat";
+        let counts = read_sources(
+            Counts::default(),
+            &[],
+            Some(text),
+            &alphabet,
+            &clean,
+            |_| Ok(()),
+        )
+        .unwrap();
+        let (lang, words, quintgrams) = counts.build(UPPER.to_string(), String::new()).unwrap();
+
+        // n-grams and word bigrams are counted across line breaks
+        let loaded = reload(&counts);
+        for n in 1..=MAX_NGRAM {
+            assert_eq!(loaded.ngrams(n), counts.ngrams(n));
+            assert_eq!(loaded.ngrams(n).values().sum::<usize>(), 26 + 1 - n);
+        }
+        assert_eq!(loaded.ngrams(3)["tsa"], 2);
+        assert_eq!(loaded.word_bigrams.values().sum::<usize>(), 8);
+        assert_eq!(
+            loaded.word_bigrams[&("cat".to_string(), "sat".to_string())],
+            2
+        );
+
+        let mut loaded = reload(&words);
+        loaded.set_alphabet(alphabet);
+        loaded.set_bigrams(reload(words.bigrams()));
+        assert_eq!(loaded.counts().map(|(_, n)| n).sum::<usize>(), 9);
+        assert_eq!(
+            segment::log_likelihood(text, &loaded),
+            segment::log_likelihood(text, &words)
+        );
+
+        let cp = lang
+            .with_alphabet(AlphabetLen::Any)
+            .code_points(text)
+            .map(|x| x as usize)
+            .collect::<Vec<_>>();
+        assert_eq!(reload(&quintgrams).score(&cp), quintgrams.score(&cp));
+
+        let mut summary = Vec::new();
+        write_summary(&mut summary, &reload(&counts)).unwrap();
+        assert_eq!(
+            String::from_utf8(summary).unwrap(),
+            "letters\t26\ndistinct letters\t9\nexpected ioc\t0.141538\nentropy\t2.811231\n"
+        );
+    }
+
+    #[test]
+    fn splits_unicode_words() {
+        let any = Alphabet::default();
+
+        assert_eq!(
+            words("Καλημέρα, ΚΌΣΜΕ! Don't—stop", &any),
+            ["καλημέρα", "κόσμε", "don't", "stop"]
+        );
+        assert_eq!(
+            words(
+                "GROẞE Straße",
+                &Alphabet::new("ABCDEFGHIJKLMNOPQRSTUVWXYZẞ", "abcdefghijklmnopqrstuvwxyzß")
+            ),
+            ["große", "straße"]
+        );
+        assert_eq!(
+            words("café", &Alphabet::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ", "")),
+            ["caf"]
+        );
+    }
+
+    #[test]
+    fn lang_text_repeats_weighted_lines() {
+        let alphabet = Alphabet::new(UPPER, "");
+        let mut counts = Counts::default();
+        counts.add_line("abcd", &alphabet, 3);
+        counts.end_source();
+        counts.add_line("wxyz", &alphabet, 1);
+        counts.end_source();
+
+        assert_eq!(counts.lang_text(), "abcd\nabcd\nabcd\nwxyz\n");
+    }
+
+    /// Corpora too large for the lang's text are scaled down, keeping the
+    /// proportions of their quadgrams and every quadgram seen.
+    #[test]
+    fn lang_text_is_scaled_over_every_quadgram() {
+        let mut counts = Counts::default();
+        let quadgram = |s: &str| pack(&s.chars().collect::<Vec<_>>());
+        counts.ngrams[3] = HashMap::from([
+            (quadgram("aaaa"), 3 * LANG_LETTERS),
+            (quadgram("bbbb"), LANG_LETTERS),
+            (quadgram("wxyz"), 1),
+        ]);
+
+        let text = counts.lang_text();
+        let count = |c| text.chars().filter(|&x| x == c).count();
+
+        assert!(text.len() <= LANG_LETTERS + 16);
+        assert_eq!(count('a'), 3 * LANG_LETTERS / 4 + 3);
+        assert_eq!(count('b'), LANG_LETTERS / 4 + 3);
+        assert!(text.contains("wxyz"));
+    }
+}
@@ -1,10 +1,21 @@
 //! Handles both encrypt and decrypt submodules
 
-use crate::{cli::opt::CipherCmd, util, CipherConfig, CryptCmd};
-use classic_crypto::cipher::{
-    Affine, Atbash, Caesar, Cipher, ClassicVigenere, Railfence, Rot13, Scytale, Substitution,
+use crate::{
+    cipher::Chaocipher,
+    cli::{
+        opt::{self, CipherCmd},
+        segment,
+    },
+    error::CipherError,
+    layout, util, CipherConfig, CryptCmd,
 };
-use std::fmt::Display;
+use classic_crypto::{
+    cipher::{
+        Affine, Atbash, Caesar, Cipher, ClassicVigenere, Railfence, Rot13, Scytale, Substitution,
+    },
+    lang::Lang,
+};
+use std::{fmt::Display, fs, io::Write, path::Path};
 
 pub trait CliCipher
 where
@@ -27,13 +38,127 @@ where
     }
 }
 
+impl CliCipher for Chaocipher {
+    fn encrypt(&self, msg: &str) -> String {
+        self.encrypt(msg)
+    }
+
+    fn decrypt(&self, msg: &str) -> String {
+        self.decrypt(msg)
+    }
+}
+
+/// A sequence of ciphers, applied in order when encrypting and in reverse
+/// order when decrypting.
+pub struct Pipeline<'l> {
+    stages: Vec<Box<dyn CliCipher + 'l>>,
+}
+
+impl<'l> Pipeline<'l> {
+    pub fn new(stages: Vec<Box<dyn CliCipher + 'l>>) -> Self {
+        Self { stages }
+    }
+}
+
+impl Display for Pipeline<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", stage)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CliCipher for Pipeline<'_> {
+    fn encrypt(&self, msg: &str) -> String {
+        self.stages
+            .iter()
+            .fold(msg.to_string(), |msg, stage| stage.encrypt(&msg))
+    }
+
+    fn decrypt(&self, msg: &str) -> String {
+        self.stages
+            .iter()
+            .rev()
+            .fold(msg.to_string(), |msg, stage| stage.decrypt(&msg))
+    }
+}
+
+/// Constructs the cipher described by a subcommand.
+pub fn build<'l>(lang: &'l Lang, cipher: CipherCmd) -> anyhow::Result<Box<dyn CliCipher + 'l>> {
+    Ok(match cipher {
+        CipherCmd::Affine { a, b } => Box::new(Affine::new(lang, a, b)?),
+        CipherCmd::Atbash => Box::new(Atbash::identity(lang)),
+        CipherCmd::Caesar { shift } => Box::new(Caesar::new(lang, shift)?),
+        CipherCmd::Chaocipher { left, right } => Box::new(Chaocipher::new(&left, &right)?),
+        CipherCmd::ClassicVigenere { keyword } => {
+            Box::new(ClassicVigenere::new(lang, keyword.as_str())?)
+        }
+        CipherCmd::Railfence { rails } => Box::new(Railfence::new(lang, rails)?),
+        CipherCmd::Rot13 => Box::new(Rot13::identity(lang)),
+        CipherCmd::Scytale { faces } => Box::new(Scytale::new(lang, faces)?),
+        CipherCmd::Substitution { keyword } => Box::new(Substitution::new(lang, keyword.as_str())?),
+    })
+}
+
+/// Reads the stages listed in a pipeline file.
+fn read_pipeline(path: &Path) -> anyhow::Result<Vec<CipherCmd>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| opt::parse_stage(line).map_err(|e| anyhow::anyhow!("{line}: {e}")))
+        .collect()
+}
+
+/// Copies the non-letters and letter case of `original` into `letters`, which
+/// is the result of applying a cipher to the letters of `original`. Any
+/// additional letters (e.g. padding) are appended.
+fn restore_format(original: &str, letters: &str, is_letter: impl Fn(char) -> bool) -> String {
+    let mut letters = letters.chars().filter(|&c| is_letter(c));
+    let mut restored = String::with_capacity(original.len());
+
+    for c in original.chars() {
+        if !is_letter(c) {
+            restored.push(c);
+        } else if let Some(letter) = letters.next() {
+            match c.is_lowercase() {
+                true => restored.extend(letter.to_lowercase()),
+                false => restored.extend(letter.to_uppercase()),
+            }
+        }
+    }
+
+    restored.extend(letters);
+    restored
+}
+
 enum CipherMode {
     Encrypt,
     Decrypt,
 }
 
 fn crypt(cfg: &CipherConfig, opt: CryptCmd, mode: CipherMode) -> anyhow::Result<()> {
-    let CryptCmd { cipher, lang, text } = opt;
+    let CryptCmd {
+        cipher,
+        stages,
+        pipeline,
+        preserve_format,
+        lang,
+        text,
+        io,
+        segment,
+        layout,
+    } = opt;
+
+    let words = match segment.segment {
+        true => Some(cfg.load_words_or_selected(lang.clone())?),
+        false => None,
+    };
 
     let lang = &match lang {
         Some(lang) => match cfg.load_lang(&lang) {
@@ -42,25 +167,45 @@ fn crypt(cfg: &CipherConfig, opt: CryptCmd, mode: CipherMode) -> anyhow::Result<
         },
         None => cfg.load_selected(),
     }?;
-    let text = util::unwrap_or_stdin(text)?;
-    let cipher: Box<dyn CliCipher> = match cipher {
-        CipherCmd::Affine { a, b } => Box::new(Affine::new(lang, a, b)?),
-        CipherCmd::Atbash => Box::new(Atbash::identity(lang)),
-        CipherCmd::Caesar { shift } => Box::new(Caesar::new(lang, shift)?),
-        CipherCmd::ClassicVigenere { keyword } => {
-            Box::new(ClassicVigenere::new(lang, keyword.as_str())?)
-        }
-        CipherCmd::Railfence { rails } => Box::new(Railfence::new(lang, rails)?),
-        CipherCmd::Rot13 => Box::new(Rot13::identity(lang)),
-        CipherCmd::Scytale { faces } => Box::new(Scytale::new(lang, faces)?),
-        CipherCmd::Substitution { keyword } => Box::new(Substitution::new(lang, keyword.as_str())?),
+    let texts = util::read_inputs(text, &io.input)?;
+    let mut out = util::output(io.output.as_deref())?;
+
+    let mut cmds = match pipeline {
+        Some(path) => read_pipeline(&path)?,
+        None => Vec::new(),
     };
-    let msg = match mode {
-        CipherMode::Encrypt => cipher.encrypt(&text),
-        CipherMode::Decrypt => cipher.decrypt(&text),
+    cmds.extend(stages);
+    cmds.extend(cipher);
+
+    let cipher: Box<dyn CliCipher> = match cmds.len() {
+        0 => return Err(CipherError::NoCipher.into()),
+        1 => build(lang, cmds.remove(0))?,
+        _ => Box::new(Pipeline::new(
+            cmds.into_iter()
+                .map(|cmd| build(lang, cmd))
+                .collect::<anyhow::Result<_>>()?,
+        )),
     };
+    let is_letter = util::is_letter(lang);
+
+    for text in texts {
+        let input = match preserve_format {
+            true => text.chars().filter(|&c| is_letter(c)).collect(),
+            false => text.clone(),
+        };
+
+        let msg = match mode {
+            CipherMode::Encrypt => cipher.encrypt(&input),
+            CipherMode::Decrypt => cipher.decrypt(&input),
+        };
+        let msg = match preserve_format {
+            true => restore_format(&text, &msg, &is_letter),
+            false => msg,
+        };
+        let msg = segment::segment_output(&segment, words.as_ref(), &msg);
 
-    println!("{}", msg.trim_end());
+        writeln!(out, "{}", layout::layout(&layout, &msg, &is_letter))?;
+    }
 
     Ok(())
 }
@@ -74,3 +219,45 @@ pub fn encrypt(cfg: &CipherConfig, encrypt_opt: CryptCmd) -> anyhow::Result<()>
 pub fn decrypt(cfg: &CipherConfig, decrypt_opt: CryptCmd) -> anyhow::Result<()> {
     crypt(cfg, decrypt_opt, CipherMode::Decrypt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_format_copies_case_and_punctuation() {
+        let restored = restore_format("Hello, world!", "URYYBJBEYQ", |c| c.is_alphabetic());
+        assert_eq!(restored, "Uryyb, jbeyq!");
+    }
+
+    #[test]
+    fn restore_format_appends_padding() {
+        let restored = restore_format("ab c", "XYZQQ", |c| c.is_alphabetic());
+        assert_eq!(restored, "xy zQQ");
+    }
+
+    const PLAIN: &str = "WEAREDISCOVEREDFLEEATONCE";
+
+    fn stage(spec: &str) -> CipherCmd {
+        opt::parse_stage(spec).unwrap()
+    }
+
+    #[test]
+    fn pipeline_decrypts_in_reverse() {
+        let lang = Lang::new(
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZ".to_string(),
+            "abcdefghijklmnopqrstuvwxyz".to_string(),
+            PLAIN,
+        )
+        .unwrap();
+        let substitution = || build(&lang, stage("substitution -k zebras")).unwrap();
+        let caesar = || build(&lang, stage("caesar --shift 3")).unwrap();
+
+        let pipeline = Pipeline::new(vec![caesar(), substitution()]);
+        let ciphertext = pipeline.encrypt(PLAIN);
+
+        assert_eq!(ciphertext, substitution().encrypt(&caesar().encrypt(PLAIN)));
+        assert_ne!(ciphertext, caesar().encrypt(&substitution().encrypt(PLAIN)));
+        assert_eq!(pipeline.decrypt(&ciphertext), PLAIN);
+    }
+}
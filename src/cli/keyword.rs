@@ -0,0 +1,384 @@
+//! Recovers the keyword which generated a mixed alphabet.
+//!
+//! Keyed alphabets are usually made by writing a keyword without repeated
+//! letters followed by the rest of the alphabet in order. The keyed alphabet
+//! may be the ciphertext alphabet (K2), the plaintext alphabet (K1) or both
+//! (K3), and may be shifted against the other alphabet. Columnar mixed
+//! alphabets write the keyed alphabet in rows under the keyword and read it
+//! off by columns.
+
+use crate::{cli::segment::Words, error::CipherError, CipherConfig};
+use std::{collections::HashMap, fmt};
+
+/// A keyed alphabet must end with at least this many letters in alphabetical
+/// order, otherwise any permutation would appear to have a keyword.
+const MIN_REMAINING: usize = 4;
+
+/// The way in which a keyword generated an alphabet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construction {
+    /// Keyed plaintext alphabet, straight ciphertext alphabet.
+    K1,
+    /// Straight plaintext alphabet, keyed ciphertext alphabet.
+    K2,
+    /// The same keyed alphabet for both, shifted.
+    K3,
+    /// A columnar mixed ciphertext alphabet, with the columns read from left
+    /// to right.
+    Columnar,
+    /// A columnar mixed ciphertext alphabet, with the columns read in the
+    /// alphabetical order of the keyword.
+    ColumnarOrdered,
+}
+
+impl fmt::Display for Construction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Construction::K1 => "K1",
+                Construction::K2 => "K2",
+                Construction::K3 => "K3",
+                Construction::Columnar => "columnar",
+                Construction::ColumnarOrdered => "ordered columnar",
+            }
+        )
+    }
+}
+
+/// A possible keyword for a mixed alphabet.
+#[derive(Debug, Clone)]
+pub struct Keyword {
+    pub keyword: String,
+    pub construction: Construction,
+    /// How far the keyed alphabet is shifted against the other alphabet.
+    pub shift: usize,
+    /// The keyword's score in the word list, if it is a word.
+    pub score: Option<f32>,
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, shift {}",
+            self.keyword, self.construction, self.shift
+        )?;
+
+        match self.score {
+            Some(_) => write!(f, ")"),
+            None => write!(f, ", not a known word)"),
+        }
+    }
+}
+
+pub fn keyword(
+    cfg: &CipherConfig,
+    alphabet: String,
+    all: bool,
+    lang: Option<String>,
+) -> anyhow::Result<()> {
+    let letters = cfg.letters_or_selected(lang.clone())?;
+    let words = cfg.load_words_or_selected(lang)?;
+    let alphabet = alphabet.trim().to_uppercase().chars().collect::<Vec<_>>();
+
+    if !is_permutation(&alphabet, &letters) {
+        return Err(CipherError::InvalidAlphabet.into());
+    }
+
+    let keywords = recover(&letters, &alphabet, &words);
+
+    if keywords.is_empty() {
+        println!("no keyword found");
+    }
+
+    for keyword in keywords.iter().take(if all { usize::MAX } else { 1 }) {
+        println!("{keyword}");
+    }
+
+    Ok(())
+}
+
+/// Whether `alphabet` contains each of `letters` exactly once.
+pub fn is_permutation(alphabet: &[char], letters: &[char]) -> bool {
+    let mut sorted = alphabet.to_vec();
+    let mut letters = letters.to_vec();
+    sorted.sort_unstable();
+    letters.sort_unstable();
+    sorted == letters
+}
+
+/// Finds the keywords which could have generated `alphabet`, the ciphertext
+/// letter for each of `letters`, most plausible first. Keywords in the word
+/// list are preferred, then more common words.
+pub fn recover(letters: &[char], alphabet: &[char], words: &Words) -> Vec<Keyword> {
+    let targets = targets(letters, alphabet);
+
+    // words by their letters without repeats, keeping the most common
+    let mut dictionary: HashMap<Vec<char>, (&str, f32)> = HashMap::new();
+    for (word, score) in words.iter() {
+        let key = without_repeats(word, letters);
+        match dictionary.get(&key) {
+            Some(&(_, best)) if best >= score => {}
+            _ => {
+                dictionary.insert(key, (word, score));
+            }
+        }
+    }
+
+    let mut keywords = Vec::new();
+
+    for &(ref keyed, construction, shift) in &targets {
+        let prefix = match keyword_len(keyed, letters) {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+
+        // any longer prefix is also followed by letters in order
+        for len in prefix..=keyed.len() {
+            if let Some(&(word, score)) = dictionary.get(&keyed[..len]) {
+                keywords.push(Keyword {
+                    keyword: word.to_uppercase(),
+                    construction,
+                    shift,
+                    score: Some(score),
+                });
+            }
+        }
+
+        // keywords are rarely longer than the letters which follow them
+        if prefix > 0 && prefix <= keyed.len() / 2 {
+            keywords.push(Keyword {
+                keyword: keyed[..prefix].iter().collect(),
+                construction,
+                shift,
+                score: None,
+            });
+        }
+    }
+
+    let columnar_targets = targets
+        .iter()
+        .filter(|(_, construction, _)| *construction == Construction::K2)
+        .map(|(keyed, _, shift)| (keyed.as_slice(), *shift))
+        .collect::<HashMap<_, _>>();
+
+    for (key, &(word, score)) in &dictionary {
+        for (ordered, construction) in [
+            (false, Construction::Columnar),
+            (true, Construction::ColumnarOrdered),
+        ] {
+            let columnar = columnar_alphabet(key, letters, ordered);
+
+            if let Some(&shift) = columnar_targets.get(columnar.as_slice()) {
+                keywords.push(Keyword {
+                    keyword: word.to_uppercase(),
+                    construction,
+                    shift,
+                    score: Some(score),
+                });
+            }
+        }
+    }
+
+    keywords.sort_by(|a, b| {
+        let a_score = a.score.unwrap_or(f32::NEG_INFINITY);
+        let b_score = b.score.unwrap_or(f32::NEG_INFINITY);
+        b_score
+            .partial_cmp(&a_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.keyword.len().cmp(&b.keyword.len()))
+            .then(a.shift.cmp(&b.shift))
+    });
+    keywords.dedup_by(|a, b| a.keyword == b.keyword && a.construction == b.construction);
+
+    keywords
+}
+
+/// The alphabets which would be keyed, if `alphabet` came from each of the
+/// constructions and shifts.
+fn targets(letters: &[char], alphabet: &[char]) -> Vec<(Vec<char>, Construction, usize)> {
+    let n = letters.len();
+    let index = |c: char| letters.iter().position(|&l| l == c).unwrap_or_default();
+
+    // the plaintext letter for each ciphertext letter
+    let mut inverse = letters.to_vec();
+    for (i, &c) in alphabet.iter().enumerate() {
+        inverse[index(c)] = letters[i];
+    }
+
+    let mut targets = Vec::new();
+
+    for shift in 0..n {
+        targets.push((rotate(alphabet, shift), Construction::K2, shift));
+        targets.push((rotate(&inverse, shift), Construction::K1, shift));
+    }
+
+    // In K3 each plaintext letter is replaced by the letter `shift` places
+    // further along the keyed alphabet, so following the substitution from
+    // any letter visits the keyed alphabet in steps of `shift`. This only
+    // recovers the whole alphabet when the steps visit every letter.
+    let mut cycle = vec![letters[0]];
+    while cycle.len() < n {
+        let next = alphabet[index(cycle[cycle.len() - 1])];
+        if next == cycle[0] {
+            break;
+        }
+        cycle.push(next);
+    }
+
+    if cycle.len() == n {
+        for shift in (1..n).filter(|&shift| gcd(shift, n) == 1) {
+            let step = (1..n).find(|&s| s * shift % n == 1).unwrap_or(1);
+            let keyed = (0..n).map(|j| cycle[j * step % n]).collect::<Vec<_>>();
+
+            for offset in 0..n {
+                targets.push((rotate(&keyed, offset), Construction::K3, shift));
+            }
+        }
+    }
+
+    targets
+}
+
+/// The length of the shortest prefix of `keyed` which is followed only by
+/// letters in alphabetical order, if enough letters follow it.
+fn keyword_len(keyed: &[char], letters: &[char]) -> Option<usize> {
+    let index = |c: &char| letters.iter().position(|l| l == c);
+    let mut len = keyed.len();
+
+    while len > 1 && index(&keyed[len - 2]) < index(&keyed[len - 1]) {
+        len -= 1;
+    }
+    len = len.saturating_sub(1);
+
+    match keyed.len() - len >= MIN_REMAINING {
+        true => Some(len),
+        false => None,
+    }
+}
+
+/// Writes the keyed alphabet in rows under the keyword, and reads it off by
+/// columns, either from left to right or in alphabetical order of the
+/// keyword.
+fn columnar_alphabet(keyword: &[char], letters: &[char], ordered: bool) -> Vec<char> {
+    let width = keyword.len().max(1);
+    let keyed = keyed_alphabet(keyword, letters);
+
+    let mut columns = (0..width).collect::<Vec<_>>();
+    if ordered {
+        columns.sort_by_key(|&i| letters.iter().position(|&l| l == keyword[i]));
+    }
+
+    columns
+        .into_iter()
+        .flat_map(|column| keyed.iter().skip(column).step_by(width).copied())
+        .collect()
+}
+
+/// The keyword followed by the remaining letters in order.
+fn keyed_alphabet(keyword: &[char], letters: &[char]) -> Vec<char> {
+    let mut keyed = keyword.to_vec();
+    keyed.extend(letters.iter().filter(|l| !keyword.contains(l)));
+    keyed
+}
+
+/// The letters of `word` in uppercase, without repeats or letters outside of
+/// `letters`.
+fn without_repeats(word: &str, letters: &[char]) -> Vec<char> {
+    let mut seen = Vec::new();
+
+    for c in word.chars().flat_map(char::to_uppercase) {
+        if letters.contains(&c) && !seen.contains(&c) {
+            seen.push(c);
+        }
+    }
+
+    seen
+}
+
+fn rotate(alphabet: &[char], shift: usize) -> Vec<char> {
+    let mut rotated = alphabet.to_vec();
+    rotated.rotate_left(shift % alphabet.len().max(1));
+    rotated
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        b => gcd(b, a % b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabet::Alphabet;
+
+    fn letters() -> Vec<char> {
+        ('A'..='Z').collect()
+    }
+
+    fn words() -> Words {
+        let counts = [("the", 100), ("kryptos", 5), ("zebras", 3)]
+            .into_iter()
+            .map(|(word, n)| (word.to_string(), n))
+            .collect();
+
+        Words::from_counts(counts, &Alphabet::default())
+    }
+
+    /// The ciphertext letter for each letter, when the plaintext letters
+    /// `plain` are replaced by the ciphertext letters `cipher`.
+    fn substitution(plain: &[char], cipher: &[char]) -> Vec<char> {
+        letters()
+            .iter()
+            .map(|l| cipher[plain.iter().position(|p| p == l).unwrap()])
+            .collect()
+    }
+
+    fn best(alphabet: &[char]) -> (String, Construction, usize) {
+        let keyword = recover(&letters(), alphabet, &words()).remove(0);
+        (keyword.keyword, keyword.construction, keyword.shift)
+    }
+
+    #[test]
+    fn recovers_k1() {
+        let keyed = keyed_alphabet(&without_repeats("kryptos", &letters()), &letters());
+        let alphabet = substitution(&keyed, &letters());
+
+        assert_eq!(
+            best(&alphabet),
+            ("KRYPTOS".to_string(), Construction::K1, 0)
+        );
+    }
+
+    #[test]
+    fn recovers_k2() {
+        let keyed = keyed_alphabet(&without_repeats("kryptos", &letters()), &letters());
+
+        assert_eq!(best(&keyed), ("KRYPTOS".to_string(), Construction::K2, 0));
+    }
+
+    #[test]
+    fn recovers_k3() {
+        let keyed = keyed_alphabet(&without_repeats("kryptos", &letters()), &letters());
+        let alphabet = substitution(&keyed, &rotate(&keyed, 3));
+
+        assert_eq!(
+            best(&alphabet),
+            ("KRYPTOS".to_string(), Construction::K3, 3)
+        );
+    }
+
+    #[test]
+    fn recovers_columnar() {
+        let alphabet = columnar_alphabet(&without_repeats("zebras", &letters()), &letters(), false);
+
+        assert_eq!(
+            best(&alphabet),
+            ("ZEBRAS".to_string(), Construction::Columnar, 0)
+        );
+    }
+}
@@ -1,5 +1,14 @@
-use crate::{util, CipherConfig, Lang, LangCmd};
-use std::io::{stdin, stdout, Read, Write};
+use crate::{
+    alphabet::Alphabet,
+    cli::corpus::{self, Counts},
+    error::CipherError,
+    util, CipherConfig, LangCmd,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{stdin, stdout, Read, Write},
+};
 
 use super::segment::Words;
 
@@ -17,16 +26,51 @@ pub fn lang(cfg: &mut CipherConfig, lang_opt: LangCmd) -> anyhow::Result<()> {
             upper,
             lower,
             corpus,
+            file,
+            clean,
         } => {
-            let corpus = match corpus {
-                Some(corpus) => corpus,
-                None => util::stdin_to_string()?,
-            };
+            let alphabet = Alphabet::new(&upper, &lower);
+            let counts = corpus::read_sources(
+                Counts::default(),
+                &file,
+                corpus.as_deref(),
+                &alphabet,
+                &clean,
+                |_| Ok(()),
+            )?;
+
+            let letters = (upper.clone(), lower.clone());
+            let (lang, words, quintgrams) = counts.build(upper, lower)?;
+
+            cfg.add_lang(
+                name,
+                (&letters.0, &letters.1),
+                &lang,
+                &words,
+                &quintgrams,
+                &counts,
+            )?;
+        }
+        LangCmd::Update {
+            name,
+            corpus,
+            file,
+            clean,
+        } => {
+            let (upper, lower) = cfg.letters(&name)?;
+            let alphabet = Alphabet::new(&upper, &lower);
+            let counts = corpus::read_sources(
+                cfg.load_counts(&name)?,
+                &file,
+                corpus.as_deref(),
+                &alphabet,
+                &clean,
+                |_| Ok(()),
+            )?;
 
-            let lang = Lang::new(upper, lower, &corpus)?;
-            let words = Words::new(&corpus);
+            let (lang, words, quintgrams) = counts.build(upper, lower)?;
 
-            cfg.add_lang(name, &lang, &words)?;
+            cfg.update_lang(&name, lang, &words, &quintgrams, &counts)?;
         }
         LangCmd::List => {
             let selected = cfg.selected_lang();
@@ -75,6 +119,66 @@ pub fn lang(cfg: &mut CipherConfig, lang_opt: LangCmd) -> anyhow::Result<()> {
                 cfg.rm_lang(name)?;
             }
         }
+        LangCmd::ImportWords {
+            name,
+            freqs,
+            dictionary,
+            count,
+            merge,
+        } => {
+            // importing nothing would replace the word list with an empty one
+            if freqs.is_empty() && dictionary.is_empty() {
+                return Err(CipherError::NoWordSources.into());
+            }
+
+            // langs added before their letters were recorded use any letter
+            let alphabet = cfg
+                .alphabet_or_selected(Some(name.clone()))
+                .unwrap_or_default();
+            let mut counts = HashMap::new();
+            let mut old = None;
+
+            if merge {
+                let words = cfg.load_words(&name)?;
+                add_counts(&mut counts, words.counts().map(|(w, n)| (w.to_string(), n)));
+                old = Some(words);
+            }
+            for path in freqs {
+                let freqs = corpus::read_frequencies(&fs::read_to_string(path)?, &alphabet)?;
+                add_counts(&mut counts, freqs);
+            }
+            for path in dictionary {
+                let words = corpus::read_dictionary(&fs::read_to_string(path)?, &alphabet, count);
+                add_counts(&mut counts, words);
+            }
+
+            // the word bigrams only describe the words of the old list, so
+            // are kept if it is merged and dropped if it is replaced
+            let mut words = Words::from_counts(counts, &alphabet);
+            if let Some(old) = old {
+                words.set_bigrams(old.into_bigrams());
+            }
+
+            cfg.save_words(&name, &words)?;
+        }
+        LangCmd::UnknownWords {
+            name,
+            penalty,
+            weight,
+        } => {
+            let (penalty, weight) = cfg.set_unknown_words(&name, penalty, weight)?;
+            println!("penalty: {penalty}, weight: {weight}");
+        }
+        LangCmd::ExportWords { name, output } => {
+            let words = cfg.load_words(&name)?;
+            let mut counts = words.counts().collect::<Vec<_>>();
+            counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+            let mut out = util::output(output.as_deref())?;
+            for (word, count) in counts {
+                writeln!(out, "{word}\t{count}")?;
+            }
+        }
         #[allow(unused)]
         LangCmd::Alphabet {
             upper,
@@ -96,3 +200,13 @@ pub fn lang(cfg: &mut CipherConfig, lang_opt: LangCmd) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Adds the counts of each word in `words` to `counts`.
+fn add_counts(
+    counts: &mut HashMap<String, usize>,
+    words: impl IntoIterator<Item = (String, usize)>,
+) {
+    for (word, count) in words {
+        *counts.entry(word).or_insert(0) += count;
+    }
+}
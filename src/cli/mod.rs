@@ -1,10 +1,13 @@
+pub mod batch;
 pub mod completions;
 pub mod corpus;
 pub mod crypt;
+pub mod keyword;
 pub mod lang;
 pub mod opt;
 pub mod segment;
 pub mod solve;
 pub mod stats;
+pub mod workbench;
 
 pub use opt::*;
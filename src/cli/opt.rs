@@ -1,5 +1,5 @@
 use clap::{ArgEnum, Args, Parser, Subcommand};
-use std::path::PathBuf;
+use std::{convert::Infallible, path::PathBuf, str::FromStr};
 
 /// Encrypt, decrypt and solve classical ciphers.
 #[derive(Parser, Debug)]
@@ -9,12 +9,26 @@ use std::path::PathBuf;
 pub enum Opt {
     /// Process a text corpus
     Corpus {
-        /// Filename to read (or use stdin)
+        /// Files or directories to read, or stdin if there are none. Each may
+        /// be followed by `=WEIGHT` to count it that many times, and files
+        /// ending in `.gz` are decompressed
         #[clap(long, short)]
-        file: Option<PathBuf>,
+        file: Vec<Source>,
         /// Output directory
         #[clap(long, short)]
         out: PathBuf,
+        /// The lang whose letters make up words. If not present, the
+        /// selected lang is used, or any letter if no lang is selected
+        #[clap(short, long)]
+        lang: Option<String>,
+        /// Uppercase alphabet to count instead of the lang's letters
+        #[clap(short, long, requires("lower"))]
+        upper: Option<String>,
+        /// Lowercase alphabet to count instead of the lang's letters
+        #[clap(long, requires("upper"))]
+        lower: Option<String>,
+        #[clap(flatten)]
+        clean: CleanOpt,
     },
     /// Perform text segmentation
     Segment {
@@ -24,6 +38,20 @@ pub enum Opt {
         /// If present, overrides the selected lang and uses the value given
         #[clap(global = true, short, long)]
         lang: Option<String>,
+        /// Show this many of the most likely segmentations, with their log
+        /// probabilities
+        #[clap(long)]
+        n_best: Option<usize>,
+        /// Follow each word with its score in the word list, marking words
+        /// which are not in it
+        #[clap(long)]
+        show_scores: bool,
+        /// Only split the runs of letters, keeping every other character and
+        /// the case of the letters in place
+        #[clap(long, conflicts_with_all(&["n-best", "show-scores"]))]
+        keep_format: bool,
+        #[clap(flatten)]
+        io: IoOpt,
     },
     /// Generate completion scripts
     Completions {
@@ -48,6 +76,8 @@ pub enum Opt {
         /// Text to analyse. If not present then read from stdin
         #[clap(global = true, short, long)]
         text: Option<String>,
+        #[clap(flatten)]
+        io: IoOpt,
         #[clap(subcommand)]
         cmd: StatsCmd,
     },
@@ -74,27 +104,245 @@ pub enum Opt {
         /// Display the key once solved
         #[clap(global = true, short = 'k', long)]
         show_key: bool,
+        /// Display the keyword which generated the key, if the key is a
+        /// mixed alphabet
+        #[clap(global = true, long)]
+        show_keyword: bool,
         /// Hide the plaintext once solved
         #[clap(global = true, short = 'T', long)]
         no_plain: bool,
-        /// Control the scoring statistics used to break the cipher
+        /// Control the scoring statistics used to break the cipher.
+        /// Quintgrams and words can't be used with classic-vigenere
         #[clap(global = true, arg_enum, short = 's', long = "size")]
         stats_size: Option<StatsSizeOpt>,
+        /// Add the log likelihood of the words found by segmenting the
+        /// plaintext, multiplied by this weight, to the n-gram score. Can't
+        /// be used with classic-vigenere
+        #[clap(global = true, long)]
+        word_weight: Option<f32>,
+        /// Try each word in the language's word list as the keyword, rather
+        /// than searching for the key. Only for ciphers keyed by a keyword,
+        /// and every keyword is tried once, so restarts can't be controlled
+        #[clap(
+            global = true,
+            long,
+            conflicts_with_all(&["threads", "seed", "timeout", "progress"])
+        )]
+        dictionary: bool,
+        /// Read the keywords to try with `--dictionary` from this file
+        /// instead, separated by whitespace
+        #[clap(global = true, long, requires("dictionary"), parse(from_os_str))]
+        wordlist: Option<PathBuf>,
+        /// Number of independent solver restarts to run in parallel. The
+        /// best solution is kept
+        #[clap(global = true, long, default_value = "1")]
+        threads: usize,
+        /// Seed for the random number generator, so that solves can be
+        /// reproduced. Each restart seeds only its own thread, and the number
+        /// of restarts run with `--timeout` varies, so the two don't combine
+        #[clap(global = true, long)]
+        seed: Option<u64>,
+        /// Keep restarting the solver until this many seconds have passed,
+        /// then use the best solution found so far. Solvers which don't use
+        /// random numbers are only run once
+        #[clap(global = true, long)]
+        timeout: Option<u64>,
+        /// Show the number of restarts and search iterations, the best score
+        /// and a preview of the best plaintext on stderr while solving
+        #[clap(global = true, long)]
+        progress: bool,
         /// If present, overrides the selected lang and uses the value given
         #[clap(global = true, short, long)]
         lang: Option<String>,
         /// The text to solve, if not specified then read from stdin
         #[clap(global = true, short, long)]
         text: Option<String>,
+        #[clap(flatten)]
+        io: IoOpt,
+        #[clap(flatten)]
+        segment: SegmentOpt,
+        #[clap(flatten)]
+        layout: LayoutOpt,
+    },
+    /// Recover the keyword which generated a mixed alphabet
+    Keyword {
+        /// The mixed alphabet, giving the ciphertext letter for each
+        /// plaintext letter in order
+        #[clap(short, long)]
+        alphabet: String,
+        /// Show every possible keyword, most plausible first
+        #[clap(long)]
+        all: bool,
+        /// If present, overrides the selected lang and uses the value given
+        #[clap(short, long)]
+        lang: Option<String>,
+    },
+    /// Interactively solve a substitution cipher. Letters are set by hand,
+    /// and the solver can fill in the rest without changing them
+    Workbench {
+        /// Control the scoring statistics used by the solver
+        #[clap(arg_enum, short = 's', long = "size")]
+        stats_size: Option<StatsSizeOpt>,
+        /// Limit to the number of iterations that the solver should run for
+        #[clap(long, default_value = "2000")]
+        max_iterations: usize,
+        /// Number of times that a solution must be reached to determine that it
+        /// is the optimal solution
+        #[clap(long, default_value = "5")]
+        min_repetitions: usize,
+        /// If present, overrides the selected lang and uses the value given
+        #[clap(short, long)]
+        lang: Option<String>,
+        /// The text to solve, if not specified then read from stdin
+        #[clap(short, long)]
+        text: Option<String>,
+        #[clap(flatten)]
+        io: IoOpt,
+    },
+    /// Solve many ciphertexts in parallel, writing one JSON result per line
+    Batch {
+        /// The cipher to solve as. If not specified, each message will be
+        /// automatically solved
+        #[clap(subcommand)]
+        cipher: Option<CipherSolveCmd>,
+        /// A directory containing one ciphertext per file, or a JSON lines
+        /// file where each line is an object with a `text` and optional `id`
+        #[clap(short, long, parse(from_os_str))]
+        input: PathBuf,
+        /// File to write results to, if not specified or `-` then write to
+        /// stdout
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Number of ciphertexts to solve at once. Defaults to the number of
+        /// CPUs
+        #[clap(long)]
+        jobs: Option<usize>,
+        /// Control the scoring statistics used to break the cipher
+        #[clap(global = true, arg_enum, short = 's', long = "size")]
+        stats_size: Option<StatsSizeOpt>,
+        /// If present, overrides the selected lang and uses the value given
+        #[clap(global = true, short, long)]
+        lang: Option<String>,
     },
 }
 
+/// Controls where text is read from and where results are written
+#[derive(Args, Debug, Clone)]
+pub struct IoOpt {
+    /// Files to read text from, each of which is processed separately. `-`
+    /// reads from stdin. Ignored if `--text` is given
+    #[clap(global = true, short, long, parse(from_os_str))]
+    pub input: Vec<PathBuf>,
+    /// File to write results to, if not specified or `-` then write to
+    /// stdout
+    #[clap(global = true, short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+/// Controls whether output text is split into words
+#[derive(Args, Debug, Clone)]
+pub struct SegmentOpt {
+    /// Split the output into words with the language's word list, for texts
+    /// written without spaces
+    #[clap(global = true, long)]
+    pub segment: bool,
+    /// Capitalise the first letter of each sentence of the segmented output
+    #[clap(global = true, long, requires("segment"))]
+    pub capitalise: bool,
+    /// Only split the runs of letters, keeping every other character and the
+    /// case of the letters in place
+    #[clap(global = true, long, requires("segment"))]
+    pub keep_format: bool,
+}
+
+/// A file or directory of corpus text, with the number of times it is
+/// counted.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    pub weight: usize,
+}
+
+impl FromStr for Source {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(
+            match s.rsplit_once('=').map(|(path, w)| (path, w.parse())) {
+                Some((path, Ok(weight))) => Source {
+                    path: path.into(),
+                    weight,
+                },
+                _ => Source {
+                    path: s.into(),
+                    weight: 1,
+                },
+            },
+        )
+    }
+}
+
+/// Controls how a corpus is cleaned before it is counted
+#[derive(Args, Debug, Clone)]
+pub struct CleanOpt {
+    /// Apply all of the cleaning options
+    #[clap(long)]
+    pub clean: bool,
+    /// Remove the Project Gutenberg header, footer and licence, the front
+    /// matter before the first chapter heading, everything after "THE END"
+    /// and transcribers' notes such as illustrations
+    #[clap(long)]
+    pub strip_gutenberg: bool,
+    /// Replace typographic quotes, dashes and spaces with plain ones
+    #[clap(long)]
+    pub normalise: bool,
+    /// Remove numbered chapter headings, lines with only a number and
+    /// section breaks made of asterisks
+    #[clap(long)]
+    pub strip_headings: bool,
+    /// Replace accented letters which are not in the alphabet with the
+    /// letters they are formed from. Without an alphabet, every accented
+    /// letter is replaced
+    #[clap(long)]
+    pub fold_accents: bool,
+}
+
+/// Controls how output text is laid out
+#[derive(Args, Debug, Clone)]
+pub struct LayoutOpt {
+    /// Remove everything other than letters from the output
+    #[clap(global = true, long)]
+    pub strip: bool,
+    /// Split the output into blocks of this many characters, ignoring
+    /// whitespace
+    #[clap(global = true, long)]
+    pub group: Option<usize>,
+    /// Wrap the output to lines of at most this many columns
+    #[clap(global = true, long)]
+    pub width: Option<usize>,
+    /// Force the case of the output
+    #[clap(global = true, arg_enum, long)]
+    pub case: Option<CaseOpt>,
+    /// Prefix each line of output with its line number
+    #[clap(global = true, long)]
+    pub line_numbers: bool,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+pub enum CaseOpt {
+    Upper,
+    Lower,
+}
+
 #[derive(ArgEnum, Clone, Copy, Debug)]
 pub enum StatsSizeOpt {
     Unigrams,
     Bigrams,
     Trigrams,
     Quadgrams,
+    Quintgrams,
+    /// Score the words found by segmenting the text with the word list
+    Words,
 }
 
 #[derive(ArgEnum, Clone, Copy, Debug)]
@@ -143,9 +391,79 @@ pub enum LangCmd {
         /// Lowercase alphabet
         #[clap(short, long)]
         lower: String,
-        /// Text corpus, if not present then read from stdin
+        /// Text corpus, if not present then read from the files or stdin
         #[clap(short, long)]
         corpus: Option<String>,
+        /// Files or directories to read the corpus from. Each may be followed
+        /// by `=WEIGHT` to count it that many times, and files ending in
+        /// `.gz` are decompressed
+        #[clap(short, long, conflicts_with("corpus"))]
+        file: Vec<Source>,
+        #[clap(flatten)]
+        clean: CleanOpt,
+    },
+    /// Add more corpus to an existing language. The corpus is counted and
+    /// added to the word, word pair and letter n-gram counts stored with
+    /// the language, which all of its statistics are then rebuilt from
+    Update {
+        /// Name of the language to update
+        #[clap(short, long)]
+        name: String,
+        /// Text corpus, if not present then read from the files or stdin
+        #[clap(short, long)]
+        corpus: Option<String>,
+        /// Files or directories to read the corpus from. Each may be followed
+        /// by `=WEIGHT` to count it that many times, and files ending in
+        /// `.gz` are decompressed
+        #[clap(short, long, conflicts_with("corpus"))]
+        file: Vec<Source>,
+        #[clap(flatten)]
+        clean: CleanOpt,
+    },
+    /// Replace a language's word list with words from frequency files and
+    /// dictionaries, or add them to it. Replacing the list drops the word
+    /// bigrams counted from the lang's corpus
+    ImportWords {
+        /// Name of the language to change
+        #[clap(short, long)]
+        name: String,
+        /// Frequency files, with a word and its count on each line separated
+        /// by a tab
+        #[clap(short, long)]
+        freqs: Vec<PathBuf>,
+        /// Dictionaries, with one word on each line
+        #[clap(short, long)]
+        dictionary: Vec<PathBuf>,
+        /// The count given to each word of a dictionary
+        #[clap(short, long, default_value = "1")]
+        count: usize,
+        /// Add the words to the current word list instead of replacing it
+        #[clap(short, long)]
+        merge: bool,
+    },
+    /// Show or change how words which are not in a language's word list are
+    /// scored when segmenting
+    UnknownWords {
+        /// Name of the language
+        #[clap(short, long)]
+        name: String,
+        /// The log probability of any unknown word, which is added to the
+        /// log probability of its letters
+        #[clap(short, long, allow_hyphen_values = true)]
+        penalty: Option<f32>,
+        /// Multiplies the log probability of the letters of an unknown word
+        #[clap(short, long)]
+        weight: Option<f32>,
+    },
+    /// Write a language's word list as a frequency file, with a word and its
+    /// count on each line separated by a tab
+    ExportWords {
+        /// Name of the language to export
+        #[clap(short, long)]
+        name: String,
+        /// Output file, if not present or `-` then write to stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
     },
     /// Change an existing language, adding or overwriting a cipher
     /// alphabet
@@ -173,7 +491,7 @@ pub enum LangCmd {
     },
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone)]
 pub enum StatsCmd {
     /// Display a graph showing periodic index of coincedence
     Periodic {
@@ -223,22 +541,61 @@ pub enum StatsCmd {
     Trigram,
     /// Display the Quadgram score for the text
     Quadgram,
+    /// Display the Quintgram score for the text
+    Quintgram,
 }
 
 #[derive(Args, Debug)]
 pub struct CryptCmd {
-    /// The algorithm to use
+    /// The algorithm to use. If stages are also given, this cipher is
+    /// applied last
     #[clap(subcommand)]
-    pub cipher: CipherCmd,
+    pub cipher: Option<CipherCmd>,
+    /// A pipeline stage, written like a cipher subcommand, e.g.
+    /// `--stage "caesar -s 3"`. Stages are applied in the order given when
+    /// encrypting, and in reverse order when decrypting
+    #[clap(long = "stage", value_parser = parse_stage)]
+    pub stages: Vec<CipherCmd>,
+    /// A file listing pipeline stages, one per line. Blank lines and lines
+    /// starting with `#` are ignored. These stages are applied before any
+    /// given with `--stage`
+    #[clap(long, parse(from_os_str))]
+    pub pipeline: Option<PathBuf>,
+    /// Remove non-letters before applying the cipher, then restore spacing,
+    /// punctuation and case at their original positions. Useful for
+    /// transposition ciphers
+    #[clap(global = true, long)]
+    pub preserve_format: bool,
     /// If present, overrides the selected lang and uses the value given
     #[clap(global = true, short, long)]
     pub lang: Option<String>,
     /// The text to encrypt/decrypt, if not specified then read from stdin
     #[clap(global = true, short, long)]
     pub text: Option<String>,
+    #[clap(flatten)]
+    pub io: IoOpt,
+    #[clap(flatten)]
+    pub segment: SegmentOpt,
+    #[clap(flatten)]
+    pub layout: LayoutOpt,
 }
 
-#[derive(Subcommand, Debug)]
+/// A single stage of a cipher pipeline
+#[derive(Parser, Debug)]
+#[clap(no_binary_name = true)]
+struct Stage {
+    #[clap(subcommand)]
+    cipher: CipherCmd,
+}
+
+/// Parses a pipeline stage, written like a cipher subcommand.
+pub fn parse_stage(spec: &str) -> Result<CipherCmd, String> {
+    Stage::try_parse_from(spec.split_whitespace())
+        .map(|stage| stage.cipher)
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Subcommand, Debug, Clone)]
 pub enum CipherCmd {
     /// The Affine cipher
     Affine {
@@ -257,6 +614,15 @@ pub enum CipherCmd {
         #[clap(short, long)]
         shift: i32,
     },
+    /// The Chaocipher
+    Chaocipher {
+        /// Left (ciphertext) starting alphabet
+        #[clap(long)]
+        left: String,
+        /// Right (plaintext) starting alphabet
+        #[clap(long)]
+        right: String,
+    },
     ClassicVigenere {
         /// Keyword
         #[clap(short, long)]
@@ -292,6 +658,15 @@ pub enum CipherSolveCmd {
     Atbash,
     /// The Caesar cipher
     Caesar,
+    /// The Chaocipher. Requires a crib, ideally of 80 or more letters. The
+    /// crib position is counted in letters, and without it every position
+    /// is tried, which is much slower and less likely to succeed
+    Chaocipher {
+        /// The letters which make up both alphabets. Defaults to the lang's
+        /// letters
+        #[clap(long)]
+        alphabet: Option<String>,
+    },
     ClassicVigenere {
         /// Maximum key length to try
         #[clap(long, default_value = "30")]
@@ -313,4 +688,49 @@ pub enum CipherSolveCmd {
         #[clap(long, default_value = "5")]
         min_repetitions: usize,
     },
+    /// A Substitution cipher combined with a Railfence or Scytale
+    /// transposition. Either layer may be absent
+    SubstitutionTransposition {
+        /// Largest number of rails or faces to try
+        #[clap(long, default_value = "20")]
+        max_period: usize,
+        /// Limit to the number of iterations that the algorithm should run for
+        #[clap(long, default_value = "2000")]
+        max_iterations: usize,
+        /// Number of times that a solution must be reached to determine that it
+        /// is the optimal solution
+        #[clap(long, default_value = "5")]
+        min_repetitions: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stages() {
+        assert!(matches!(
+            parse_stage("caesar --shift 3"),
+            Ok(CipherCmd::Caesar { shift: 3 })
+        ));
+        assert!(matches!(
+            parse_stage("  substitution   -k zebras "),
+            Ok(CipherCmd::Substitution { keyword }) if keyword == "zebras"
+        ));
+        assert!(matches!(parse_stage("rot13"), Ok(CipherCmd::Rot13)));
+    }
+
+    #[test]
+    fn rejects_invalid_stages() {
+        for spec in [
+            "",
+            "enigma",
+            "caesar",
+            "caesar --shift three",
+            "rot13 --shift 3",
+        ] {
+            assert!(parse_stage(spec).is_err(), "{spec}");
+        }
+    }
 }
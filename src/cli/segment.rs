@@ -1,65 +1,360 @@
-use crate::{config::CipherConfig, util::stdin_to_string};
+use crate::{
+    alphabet::Alphabet,
+    cli::{IoOpt, SegmentOpt},
+    config::CipherConfig,
+    util,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, io::Write, iter};
 
-/// Stores the value log(f / N) for each word.
+/// Default log probability of any word which is not in the word list.
+pub const UNKNOWN_PENALTY: f32 = -10.0;
+/// Default weight of the log probability of the letters of an unknown word.
+pub const UNKNOWN_WEIGHT: f32 = 1.0;
+
+/// Stores the value ln(f / N) for each word.
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Words {
     words: HashMap<String, f32>,
+    /// The number of times each word was seen, so that the list can be
+    /// exported and merged with others.
+    counts: HashMap<String, usize>,
+    /// The letters which make up words. This is stored with the lang rather
+    /// than in the word list, and set when the word list is loaded.
+    #[serde(skip)]
+    alphabet: Alphabet,
+    /// Stored in a separate file, and set when the word list is loaded.
+    #[serde(skip)]
+    bigrams: WordBigrams,
+    /// Fitted to the words when the word list is loaded, with the
+    /// parameters stored with the lang.
+    #[serde(skip)]
+    unknown: UnknownWords,
+}
+
+/// Scores words which are not in the word list by how likely their letters
+/// are to follow each other, from the letter trigrams of the words which are,
+/// counted as many times as each word was seen.
+#[derive(Debug, Default)]
+struct UnknownWords {
+    /// Index of each letter. 0 marks the start and end of a word, and
+    /// letters which were not seen have the last index.
+    index: HashMap<char, usize>,
+    /// Number of times each trigram (a, b, c) was seen. Only the trigrams
+    /// which were seen are kept, since an alphabet of any letter has far
+    /// too many to store them all.
+    trigrams: HashMap<(usize, usize, usize), usize>,
+    /// Number of times each pair (a, b) was followed by any letter.
+    pairs: HashMap<(usize, usize), usize>,
+    size: usize,
+    /// Log probability of any unknown word.
+    penalty: f32,
+    /// Multiplies the log probability of the letters.
+    weight: f32,
 }
 
+impl UnknownWords {
+    fn new<'a>(
+        words: impl Iterator<Item = (&'a str, usize)> + Clone,
+        penalty: f32,
+        weight: f32,
+    ) -> Self {
+        let mut index = HashMap::new();
+        for c in words.clone().flat_map(|(word, _)| word.chars()) {
+            let next = index.len() + 1;
+            index.entry(c).or_insert(next);
+        }
+
+        let mut trigrams = HashMap::new();
+        let mut pairs = HashMap::new();
+        for (word, count) in words {
+            let (mut a, mut b) = (0, 0);
+            for c in word.chars().map(|c| index[&c]).chain(iter::once(0)) {
+                *trigrams.entry((a, b, c)).or_insert(0) += count;
+                *pairs.entry((a, b)).or_insert(0) += count;
+                (a, b) = (b, c);
+            }
+        }
+
+        Self {
+            size: index.len() + 2,
+            index,
+            trigrams,
+            pairs,
+            penalty,
+            weight,
+        }
+    }
+    fn indices(&self, word: &[char]) -> Vec<usize> {
+        word.iter()
+            .map(|c| self.index.get(c).copied().unwrap_or(self.index.len() + 1))
+            .collect()
+    }
+    /// The log probability of the unknown word with letters at `indices`.
+    fn score(&self, indices: &[usize]) -> f32 {
+        // no words to learn from
+        if self.trigrams.is_empty() {
+            return UNKNOWN_PENALTY * indices.len() as f32;
+        }
+
+        let (mut a, mut b) = (0, 0);
+        let mut log_prob = 0.0;
+        for &c in indices.iter().chain(iter::once(&0)) {
+            // add one to every count, so that any letter may follow any other
+            let n = self.trigrams.get(&(a, b, c)).copied().unwrap_or(0) + 1;
+            let total = self.pairs.get(&(a, b)).copied().unwrap_or(0) + self.size;
+            log_prob += (n as f32 / total as f32).ln();
+            (a, b) = (b, c);
+        }
+
+        self.penalty + self.weight * log_prob
+    }
+}
+
+/// Stores the value log(f(prev, word) / f(prev)) for each pair of words seen
+/// together, keyed by the second word.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WordBigrams {
+    log_probs: HashMap<String, HashMap<String, f32>>,
+    /// Added to a word's unigram score when it has not been seen after the
+    /// word before it. Zero when there are no bigrams.
+    backoff: f32,
+}
+
+impl WordBigrams {
+    /// Creates the bigrams from the number of times each pair of words was
+    /// seen together.
+    pub fn from_counts(counts: &HashMap<(String, String), usize>) -> Self {
+        let mut firsts = HashMap::new();
+        for ((prev, _), &n) in counts {
+            *firsts.entry(prev.as_str()).or_insert(0usize) += n;
+        }
+
+        let mut log_probs: HashMap<String, HashMap<String, f32>> = HashMap::new();
+        for ((prev, word), &n) in counts {
+            let cost = (n as f32 / firsts[prev.as_str()] as f32).ln();
+            log_probs
+                .entry(word.clone())
+                .or_default()
+                .insert(prev.clone(), cost);
+        }
+
+        Self {
+            backoff: match log_probs.is_empty() {
+                true => 0.0,
+                false => BACKOFF.ln(),
+            },
+            log_probs,
+        }
+    }
+    /// The words seen before `word`, and their scores.
+    fn predecessors(&self, word: &str) -> Option<&HashMap<String, f32>> {
+        self.log_probs.get(word)
+    }
+}
+
+/// Weight given to the unigram score of a word which has not been seen after
+/// the word before it.
+const BACKOFF: f32 = 0.4;
+
 impl Words {
-    pub fn new(corpus: &str) -> Self {
-        let words = crate::cli::corpus::words(corpus);
-        let counts = crate::cli::corpus::frequencies(words);
+    /// Creates a word list from the number of times each word was seen.
+    pub fn from_counts(counts: HashMap<String, usize>, alphabet: &Alphabet) -> Self {
         let sum = counts.values().sum::<usize>() as f32;
 
         Self {
             words: counts
-                .into_iter()
-                .map(|(k, v)| {
-                    let cost = ((v as f32) / sum * (k.len() as f32 + 1.0).ln()).ln();
-                    (k, cost)
-                })
+                .iter()
+                .map(|(k, &v)| (k.clone(), (v as f32 / sum).ln()))
                 .collect(),
+            counts,
+            alphabet: alphabet.clone(),
+            ..Self::default()
         }
     }
+    /// Creates a word list from the scores of a list saved before counts
+    /// were kept, which weighted each word by ln(len + 1). The counts are
+    /// estimated, with the rarest word seen once.
+    pub fn from_scores(words: HashMap<String, f32>) -> Self {
+        let relative = |k: &str, cost: f32| cost.exp() / (k.len() as f32 + 1.0).ln();
+        let rarest = words
+            .iter()
+            .map(|(k, &cost)| relative(k, cost))
+            .fold(f32::INFINITY, f32::min);
+
+        let counts = words
+            .iter()
+            .map(|(k, &cost)| {
+                let count = (relative(k, cost) / rarest).round().max(1.0);
+                (k.clone(), count as usize)
+            })
+            .collect();
+
+        Self::from_counts(counts, &Alphabet::default())
+    }
+    /// Fits the model which scores unknown words to the word list.
+    /// `penalty` is the log probability of any unknown word, which is added
+    /// to the log probability of its letters multiplied by `weight`.
+    pub fn fit_unknown(&mut self, penalty: f32, weight: f32) {
+        let counts = self.counts.iter().map(|(word, &n)| (word.as_str(), n));
+        self.unknown = UnknownWords::new(counts, penalty, weight);
+    }
+    /// An iterator over the words and the number of times they were seen.
+    pub fn counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.counts
+            .iter()
+            .map(|(word, &count)| (word.as_str(), count))
+    }
+    pub fn set_alphabet(&mut self, alphabet: Alphabet) {
+        self.alphabet = alphabet;
+    }
+    pub fn bigrams(&self) -> &WordBigrams {
+        &self.bigrams
+    }
+    pub fn set_bigrams(&mut self, bigrams: WordBigrams) {
+        self.bigrams = bigrams;
+    }
+    pub fn into_bigrams(self) -> WordBigrams {
+        self.bigrams
+    }
+    /// Length of the longest word, in characters.
     pub fn max_len(&self) -> usize {
-        self.words.keys().map(|k| k.len()).max().unwrap_or(1)
+        self.words
+            .keys()
+            .map(|k| k.chars().count())
+            .max()
+            .unwrap_or(1)
+    }
+    /// An iterator over the words and their scores.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.words
+            .iter()
+            .map(|(word, &score)| (word.as_str(), score))
+    }
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains_key(word)
     }
     pub fn score(&self, word: &str) -> f32 {
         match self.words.get(word) {
             Some(&score) => score,
-            None => -10.0 * word.len() as f32,
+            None => self
+                .unknown
+                .score(&self.unknown.indices(&word.chars().collect::<Vec<_>>())),
         }
     }
 }
 
+/// Splits `text` into words if `opt` asks for it. `words` must be present
+/// when it does.
+pub fn segment_output(opt: &SegmentOpt, words: Option<&Words>, text: &str) -> String {
+    let segmented = match (opt.segment, words) {
+        (true, Some(words)) if opt.keep_format => segment_keep_format(text, words),
+        (true, Some(words)) => segment_str(text, words),
+        _ => return text.to_string(),
+    };
+
+    match opt.capitalise {
+        true => capitalise(&segmented),
+        false => segmented,
+    }
+}
+
+/// Capitalises the first letter of `text` and of each sentence within it,
+/// along with the word "i".
+pub fn capitalise(text: &str) -> String {
+    let mut capitalised = String::with_capacity(text.len());
+    let mut sentence_start = true;
+    let mut chars = text.chars().peekable();
+    let mut prev = ' ';
+
+    while let Some(c) = chars.next() {
+        let standalone_i = c == 'i'
+            && prev.is_whitespace()
+            && chars.peek().filter(|c| !c.is_whitespace()).is_none();
+
+        if c.is_alphabetic() && (sentence_start || standalone_i) {
+            capitalised.extend(c.to_uppercase());
+            sentence_start = false;
+        } else {
+            capitalised.push(c);
+            sentence_start =
+                (sentence_start && !c.is_alphanumeric()) || matches!(c, '.' | '!' | '?');
+        }
+
+        prev = c;
+    }
+
+    capitalised
+}
+
 pub fn segment(
     cfg: &CipherConfig,
     text: Option<String>,
     lang: Option<String>,
+    n_best: Option<usize>,
+    show_scores: bool,
+    keep_format: bool,
+    io: IoOpt,
 ) -> anyhow::Result<()> {
     let words = cfg.load_words_or_selected(lang)?;
+    let texts = util::read_inputs(text, &io.input)?;
+    let mut out = util::output(io.output.as_deref())?;
 
-    println!(
-        "{}",
-        segment_str(
-            &match text {
-                Some(text) => text,
-                None => stdin_to_string()?,
-            },
-            &words
-        )
-    );
+    for (i, text) in texts.into_iter().enumerate() {
+        if keep_format {
+            writeln!(out, "{}", segment_keep_format(&text, &words))?;
+            continue;
+        }
+
+        let n = match n_best {
+            Some(n) if i > 0 => {
+                writeln!(out)?;
+                n
+            }
+            Some(n) => n,
+            None => 1,
+        };
+
+        for (score, split) in n_best_str(&text, &words, n) {
+            let split = match show_scores {
+                true => annotate(&split, &words),
+                false => split.join(" "),
+            };
+
+            match n_best {
+                Some(_) => writeln!(out, "{score:.2}\t{split}")?,
+                None => writeln!(out, "{split}")?,
+            }
+        }
+    }
 
     Ok(())
 }
 
+/// Joins `split` with each word followed by its score, and words which are
+/// not in the word list marked.
+fn annotate(split: &[String], words: &Words) -> String {
+    split
+        .iter()
+        .map(|word| match words.contains(word) {
+            true => format!("{word}({:.2})", words.score(word)),
+            false => format!("{word}({:.2}, unknown)", words.score(word)),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Returns a probability value for a word which should be maximised.
-fn score_word(words: &Words, word: &[u8]) -> f32 {
-    let word = std::str::from_utf8(word).unwrap();
-    words.score(word)
+/// `indices` are the word's letters in the unknown word model, and `buf` is
+/// reused to avoid allocating for every word.
+fn score_word(words: &Words, word: &[char], indices: &[usize], buf: &mut String) -> f32 {
+    buf.clear();
+    buf.extend(word);
+
+    match words.words.get(buf.as_str()) {
+        Some(&score) => score,
+        None => words.unknown.score(indices),
+    }
 }
 
 /// Finds the max of a and b.
@@ -70,45 +365,291 @@ fn max(a: (f32, usize), b: (f32, usize)) -> (f32, usize) {
     }
 }
 
-/// Algorithm inspired by: https://stackoverflow.com/questions/8870261/how-to-split-text-without-spaces-into-list-of-words/11642687#11642687
-pub fn segment_str(s: &str, words: &Words) -> String {
+/// The letters of `s` which the segmenter considers, in lowercase.
+fn letters(s: &str, alphabet: &Alphabet) -> Vec<char> {
+    s.chars().filter_map(|c| alphabet.to_lower(c)).collect()
+}
+
+/// The best split of `splits`, as (word probability, length of the last word).
+fn best_of(splits: &[(f32, usize)]) -> (f32, usize) {
+    splits
+        .iter()
+        .enumerate()
+        .fold((f32::NEG_INFINITY, 0), |best, (len, &(score, _))| {
+            max((score, len), best)
+        })
+}
+
+/// Finds the most probable split of `text` into words.
+/// best[i][len] = (word probability, length of the word before) of the best
+/// split of the first i letters which ends with a word of length len.
+fn best_splits(text: &[char], words: &Words) -> Vec<Vec<(f32, usize)>> {
     let max_len = words.max_len();
-    let text = s
-        .chars()
-        .filter(|&c| c.is_ascii_alphabetic())
-        .map(|c| c.to_ascii_lowercase() as u8)
+    let mut buf = String::new();
+    let mut prev_buf = String::new();
+    let indices = words.unknown.indices(text);
+    let mut best = vec![vec![(f32::NEG_INFINITY, 0); max_len + 1]; text.len() + 1];
+    best[0][0] = (0.0, 0);
+
+    for i in 1..=text.len() {
+        for word_len in 1..=max_len.min(i) {
+            let start = i - word_len;
+            let unigram = score_word(words, &text[start..i], &indices[start..i], &mut buf);
+
+            // back off to the unigram score after the best split so far
+            let (score, prev_len) = best_of(&best[start]);
+            let mut split = (score + words.bigrams.backoff + unigram, prev_len);
+
+            if let Some(prevs) = words.bigrams.predecessors(&buf) {
+                for prev_len in 1..=max_len.min(start) {
+                    prev_buf.clear();
+                    prev_buf.extend(&text[start - prev_len..start]);
+
+                    if let Some(&cost) = prevs.get(&prev_buf) {
+                        split = max((best[start][prev_len].0 + cost, prev_len), split);
+                    }
+                }
+            }
+
+            best[i][word_len] = split;
+        }
+    }
+
+    best
+}
+
+/// The total log probability of the words in the best segmentation of `s`.
+/// Higher values are more likely to be real text.
+pub fn log_likelihood(s: &str, words: &Words) -> f32 {
+    let text = letters(s, &words.alphabet);
+    best_of(&best_splits(&text, words)[text.len()]).0
+}
+
+/// Finds the `n` most probable splits of `text` into words.
+/// best[i][len] = (word probability, length of the word before, rank of the
+/// split before) for the best splits of the first i letters which end with a
+/// word of length len, most probable first.
+fn n_best_splits(text: &[char], words: &Words, n: usize) -> Vec<Vec<Vec<(f32, usize, usize)>>> {
+    let max_len = words.max_len();
+    let mut buf = String::new();
+    let mut prev_buf = String::new();
+    let indices = words.unknown.indices(text);
+    let mut best = vec![vec![Vec::new(); max_len + 1]; text.len() + 1];
+    best[0][0].push((0.0, 0, 0));
+
+    for i in 1..=text.len() {
+        for word_len in 1..=max_len.min(i) {
+            let start = i - word_len;
+            let unigram = score_word(words, &text[start..i], &indices[start..i], &mut buf);
+            let prevs = words.bigrams.predecessors(&buf);
+            let mut splits = Vec::new();
+
+            for prev_len in 0..=max_len.min(start) {
+                prev_buf.clear();
+                prev_buf.extend(&text[start - prev_len..start]);
+
+                // back off to the unigram score if it is better
+                let backoff = words.bigrams.backoff + unigram;
+                let cost = match prevs.and_then(|prevs| prevs.get(&prev_buf)) {
+                    Some(&cost) => cost.max(backoff),
+                    None => backoff,
+                };
+
+                for (rank, &(score, ..)) in best[start][prev_len].iter().enumerate() {
+                    splits.push((score + cost, prev_len, rank));
+                }
+            }
+
+            // ties go to the longer word before, as in `best_splits`
+            splits.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+            splits.truncate(n);
+            best[i][word_len] = splits;
+        }
+    }
+
+    best
+}
+
+/// The `n` most probable segmentations of `s`, as their log probabilities and
+/// words.
+pub fn n_best_str(s: &str, words: &Words, n: usize) -> Vec<(f32, Vec<String>)> {
+    let text = letters(s, &words.alphabet);
+    let best = n_best_splits(&text, words, n);
+
+    let mut ends = best[text.len()]
+        .iter()
+        .enumerate()
+        .flat_map(|(len, splits)| {
+            splits
+                .iter()
+                .enumerate()
+                .map(move |(rank, &(score, ..))| (score, len, rank))
+        })
         .collect::<Vec<_>>();
+    ends.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+    ends.truncate(n);
 
-    // best[n] = (word probability, length of best word ending at n+1).
-    let mut best: Vec<(f32, usize)> = Vec::with_capacity(text.len());
+    // read back through the words before each split
+    ends.into_iter()
+        .map(|(score, mut word_len, mut rank)| {
+            let mut split = Vec::new();
+            let mut length = text.len();
 
-    for i in 0..=text.len() {
-        best.push((if i == 0 { 0.0 } else { -10_000.0 }, 0));
+            while length > 0 {
+                let (_, prev_len, prev_rank) = best[length][word_len][rank];
+                length -= word_len;
+                split.push(text[length..][..word_len].iter().collect::<String>());
+                word_len = prev_len;
+                rank = prev_rank;
+            }
 
-        for word_len in 1..=max_len.min(best.len()).min(i) {
-            let word = &text[i.min(i - word_len)..i];
-            let score = match i {
-                0 => score_word(words, word),
-                i => score_word(words, word) + best[i - word_len].0,
-            };
-            best[i] = max((score, word_len), best[i]);
+            split.reverse();
+            (score, split)
+        })
+        .collect()
+}
+
+/// Algorithm inspired by: https://stackoverflow.com/questions/8870261/how-to-split-text-without-spaces-into-list-of-words/11642687#11642687
+pub fn segment_str(s: &str, words: &Words) -> String {
+    let text = letters(s, &words.alphabet);
+    let mut start = 0;
+
+    word_lens(&text, words)
+        .into_iter()
+        .map(|len| {
+            start += len;
+            text[start - len..start].iter().collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits each run of letters in `s` into words, leaving every other
+/// character and the case of the letters unchanged.
+pub fn segment_keep_format(s: &str, words: &Words) -> String {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut segmented = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let run_len = chars[i..]
+            .iter()
+            .take_while(|&&c| words.alphabet.is_letter(c))
+            .count();
+
+        if run_len == 0 {
+            segmented.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let run = &chars[i..i + run_len];
+        let text = run
+            .iter()
+            .filter_map(|&c| words.alphabet.to_lower(c))
+            .collect::<Vec<_>>();
+        let mut start = 0;
+
+        for len in word_lens(&text, words) {
+            if start > 0 {
+                segmented.push(' ');
+            }
+            segmented.extend(&run[start..start + len]);
+            start += len;
         }
+
+        i += run_len;
     }
 
+    segmented
+}
+
+/// The lengths of the words in the best segmentation of `text`, in order.
+fn word_lens(text: &[char], words: &Words) -> Vec<usize> {
+    let best = best_splits(text, words);
+
     // read from the best scores to find the correct segmentation.
-    let mut words = Vec::new();
+    let mut lens = Vec::new();
     let mut length = text.len();
+    let (_, mut word_len) = best_of(&best[length]);
 
     while length > 0 {
-        let (_, word_len) = best[length];
+        let (_, prev_len) = best[length][word_len];
         length -= word_len;
-        words.push(&text[length..][..word_len]);
+        lens.push(word_len);
+        word_len = prev_len;
     }
 
-    words.reverse();
+    lens.reverse();
+    lens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words() -> Words {
+        let counts = [
+            ("the", 50),
+            ("cat", 5),
+            ("sat", 5),
+            ("on", 20),
+            ("mat", 2),
+            ("a", 30),
+            ("at", 10),
+            ("he", 10),
+            ("eca", 1),
+            ("kitten", 1),
+        ]
+        .into_iter()
+        .map(|(word, n)| (word.to_string(), n))
+        .collect();
+
+        let mut words =
+            Words::from_counts(counts, &Alphabet::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ", ""));
+        words.fit_unknown(UNKNOWN_PENALTY, UNKNOWN_WEIGHT);
+        words
+    }
+
+    #[test]
+    fn segments_unicode_words() {
+        let counts = [("καλημέρα", 5), ("κόσμε", 5), ("große", 5), ("straße", 5)]
+            .into_iter()
+            .map(|(word, n)| (word.to_string(), n))
+            .collect();
+        let mut words = Words::from_counts(counts, &Alphabet::default());
+        words.fit_unknown(UNKNOWN_PENALTY, UNKNOWN_WEIGHT);
+
+        assert_eq!(segment_str("ΚΑΛΗΜΈΡΑΚΌΣΜΕ", &words), "καλημέρα κόσμε");
+        assert_eq!(segment_str("GROẞESTRAẞE", &words), "große straße");
+    }
 
-    let segmented = words.join(&b' ');
+    /// The unknown word "zorp" is kept whole by default. Ignoring its
+    /// letters lets it take the "s" of "sat", and making unknown words cheap
+    /// lets them swallow known words.
+    #[test]
+    fn unknown_word_parameters_change_segmentation() {
+        let mut words = words();
+        let text = "thecatzorpsatonthemat";
 
-    // guarunteed to be an ASCII string
-    String::from_utf8(segmented).unwrap()
+        assert_eq!(segment_str(text, &words), "the cat zorp sat on the mat");
+
+        words.fit_unknown(UNKNOWN_PENALTY, 0.0);
+        assert_eq!(segment_str(text, &words), "the cat zorps at on the mat");
+
+        words.fit_unknown(0.0, 0.1);
+        assert_eq!(segment_str(text, &words), "thecat zorp saton themat");
+    }
+
+    #[test]
+    fn n_best_agrees_with_segment() {
+        let words = words();
+
+        for text in ["thecatsatonthemat", "THE CAT", "athematon", "xyzzy"] {
+            let n_best = n_best_str(text, &words, 3);
+
+            assert_eq!(n_best[0].1.join(" "), segment_str(text, &words), "{text}");
+            assert!(n_best.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+        }
+    }
 }
@@ -1,74 +1,518 @@
 use crate::{
+    cipher::{
+        layered::{self, Layers},
+        partial::{self, PartialSolve},
+        Chaocipher, ChaocipherSolve, LayeredSolve,
+    },
     cli::{
         crypt::CliCipher,
+        keyword,
         opt::{CipherSolveCmd, StatsSizeOpt},
+        segment::{self, Words},
     },
+    error::CipherError,
+    layout,
+    restart::{self, Budget, Scored, Search},
+    score::Stats,
     util, CipherConfig, Opt,
 };
-use classic_crypto::{cipher::*, prelude::StatsSize};
+use classic_crypto::{
+    analysis,
+    cipher::*,
+    lang::{AlphabetLen, Lang},
+    prelude::StatsSize,
+};
+use log::{debug, warn};
+use rayon::prelude::*;
+use std::{cmp::Ordering, fs, io::Write, sync::Arc, time::Duration};
+
+/// Texts whose IoC is within this proportion of the language's expected IoC
+/// are treated as monoalphabetic when identifying the cipher.
+const AUTO_IOC_TOLERANCE: f32 = 0.2;
 
 pub fn solve(cfg: &CipherConfig, solve_opt: Opt) -> anyhow::Result<()> {
     if let Opt::Solve {
         cipher,
-        crib: _,
-        crib_pos: _,
+        crib,
+        crib_pos,
         show_key,
+        show_keyword,
         no_plain,
         stats_size,
+        word_weight,
+        dictionary,
+        wordlist,
+        threads,
+        seed,
+        timeout,
+        progress,
         lang,
         text,
+        io,
+        segment,
+        layout,
     } = solve_opt
     {
+        let keywords = match show_keyword {
+            true => Some((
+                cfg.letters_or_selected(lang.clone())?,
+                cfg.load_words_or_selected(lang.clone())?,
+            )),
+            false => None,
+        };
+        let dictionary = match (dictionary, wordlist) {
+            (true, Some(path)) => Some(
+                fs::read_to_string(path)?
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            (true, None) => Some(dictionary_words(&cfg.load_words_or_selected(lang.clone())?)),
+            (false, _) => None,
+        };
+        let words = match segment.segment {
+            true => Some(cfg.load_words_or_selected(lang.clone())?),
+            false => None,
+        };
+        // the Chaocipher's alphabets default to the lang's letters
+        let cipher = match cipher {
+            Some(CipherSolveCmd::Chaocipher { alphabet: None }) => {
+                Some(CipherSolveCmd::Chaocipher {
+                    alphabet: Some(cfg.letters_or_selected(lang.clone())?.into_iter().collect()),
+                })
+            }
+            cipher => cipher,
+        };
+        let stats = &stats_or_default(cfg, lang.clone(), stats_size, word_weight)?;
+        // only some solvers need the letters, so langs which don't record
+        // them can still be used with the others
+        let letters = &cfg.letters_or_selected(lang.clone()).unwrap_or_default();
         let lang = &cfg.load_lang_or_selected(lang)?;
-        let text = &util::unwrap_or_stdin(text)?;
-        let stats_size = match stats_size {
-            Some(StatsSizeOpt::Unigrams) => StatsSize::Unigrams,
-            Some(StatsSizeOpt::Bigrams) => StatsSize::Unigrams,
-            Some(StatsSizeOpt::Trigrams) => StatsSize::Unigrams,
-            _ => StatsSize::Quadgrams,
+        let texts = util::read_inputs(text, &io.input)?;
+        let mut out = util::output(io.output.as_deref())?;
+        let budget = Budget {
+            threads,
+            seed,
+            timeout: timeout.map(Duration::from_secs),
+            progress,
+            random: uses_random(cipher.as_ref()),
         };
-        let solution: Box<dyn CliCipher> = match cipher {
-            None => todo!(),
-            Some(opt) => match opt {
-                CipherSolveCmd::Affine => Box::new(Affine::solve(lang, text, stats_size)),
-                CipherSolveCmd::Atbash => Box::new(Atbash::solve(lang, text, ())),
-                CipherSolveCmd::Caesar => Box::new(Caesar::solve(lang, text, stats_size)),
-                CipherSolveCmd::ClassicVigenere { max_key_length } => {
-                    Box::new(ClassicVigenere::solve(
+
+        for text in &texts {
+            let solution = match &dictionary {
+                Some(keywords) => solve_dictionary(lang, text, cipher.as_ref(), keywords, stats)?,
+                None => restart::best_of(budget, |search| {
+                    let ctx = SolveContext {
                         lang,
-                        text,
-                        ClassicVigenereSolve {
-                            stats_size,
-                            max_key_length,
-                        },
-                    ))
+                        letters,
+                        stats,
+                        search,
+                    };
+
+                    solve_text(&ctx, text, cipher.as_ref(), crib.as_deref(), crib_pos)
+                })?,
+            };
+
+            if show_key {
+                writeln!(out, "{}", solution.key)?;
+            }
+
+            if let (true, Some(keyword)) = (show_keyword, &solution.keyword) {
+                writeln!(out, "{keyword}")?;
+            } else if let Some((letters, words)) = &keywords {
+                let alphabet = solution
+                    .key
+                    .trim()
+                    .to_uppercase()
+                    .chars()
+                    .collect::<Vec<_>>();
+
+                if keyword::is_permutation(&alphabet, letters) {
+                    if let Some(keyword) = keyword::recover(letters, &alphabet, words).first() {
+                        writeln!(out, "{keyword}")?;
+                    }
                 }
-                CipherSolveCmd::Railfence => Box::new(Railfence::solve(lang, text, stats_size)),
-                CipherSolveCmd::Rot13 => Box::new(Rot13::solve(lang, text, ())),
-                CipherSolveCmd::Scytale => Box::new(Scytale::solve(lang, text, stats_size)),
-                CipherSolveCmd::Substitution {
-                    max_iterations,
-                    min_repetitions,
-                } => Box::new(Substitution::solve(
-                    lang,
-                    text,
-                    SubstitutionSolve {
-                        stats_size,
-                        max_iterations,
-                        min_repetitions,
-                    },
-                )),
+            }
+
+            if !no_plain {
+                let plaintext =
+                    segment::segment_output(&segment, words.as_ref(), &solution.plaintext);
+                writeln!(
+                    out,
+                    "{}",
+                    layout::layout(&layout, &plaintext, util::is_letter(lang))
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A solved ciphertext.
+pub struct Solution {
+    /// The key, as displayed by the cipher.
+    pub key: String,
+    /// The keyword the key was built from, if the solver knows it.
+    pub keyword: Option<String>,
+    pub plaintext: String,
+    pub score: f32,
+}
+
+impl Scored for Solution {
+    fn score(&self) -> f32 {
+        self.score
+    }
+
+    fn preview(&self) -> &str {
+        &self.plaintext
+    }
+}
+
+/// What the solvers need besides the ciphertext and the cipher.
+pub struct SolveContext<'a> {
+    pub lang: &'a Lang,
+    /// The uppercase letters of the lang, which may be empty if it doesn't
+    /// record them.
+    pub letters: &'a [char],
+    pub stats: &'a Stats,
+    pub search: &'a Search,
+}
+
+/// Whether solving `cipher` uses random numbers, so that restarts can find
+/// different solutions. Identifying the cipher may try a substitution.
+fn uses_random(cipher: Option<&CipherSolveCmd>) -> bool {
+    matches!(
+        cipher,
+        None | Some(
+            CipherSolveCmd::Substitution { .. } | CipherSolveCmd::SubstitutionTransposition { .. }
+        )
+    )
+}
+
+/// Solves `text` as the cipher given by `cipher`, or identifies the cipher if
+/// it is `None`.
+pub fn solve_text(
+    ctx: &SolveContext,
+    text: &str,
+    cipher: Option<&CipherSolveCmd>,
+    crib: Option<&str>,
+    crib_pos: Option<usize>,
+) -> anyhow::Result<Solution> {
+    let solution = match cipher {
+        None => solve_auto(ctx, text)?,
+        Some(cipher) => solve_cipher(ctx, text, cipher, crib, crib_pos)?,
+    };
+    let plaintext = solution.decrypt(text);
+
+    Ok(Solution {
+        key: solution.to_string(),
+        keyword: None,
+        score: ctx.stats.score(ctx.lang, &plaintext),
+        plaintext,
+    })
+}
+
+/// Solves `text` by trying each of `keywords` as the key of `cipher`, and
+/// keeping the keyword whose decryption scores highest. Earlier keywords win
+/// ties, so that a keyword is preferred over its repetitions.
+pub fn solve_dictionary(
+    lang: &Lang,
+    text: &str,
+    cipher: Option<&CipherSolveCmd>,
+    keywords: &[String],
+    stats: &Stats,
+) -> anyhow::Result<Solution> {
+    let build = |keyword: &str| -> Option<Box<dyn CliCipher + '_>> {
+        match cipher {
+            Some(CipherSolveCmd::ClassicVigenere { .. }) => ClassicVigenere::new(lang, keyword)
+                .ok()
+                .map(|c| Box::new(c) as _),
+            Some(CipherSolveCmd::Substitution { .. }) => Substitution::new(lang, keyword)
+                .ok()
+                .map(|c| Box::new(c) as _),
+            _ => None,
+        }
+    };
+
+    if !matches!(
+        cipher,
+        Some(CipherSolveCmd::ClassicVigenere { .. } | CipherSolveCmd::Substitution { .. })
+    ) {
+        return Err(CipherError::NoDictionaryAttack.into());
+    }
+
+    keywords
+        .par_iter()
+        .enumerate()
+        .filter_map(|(i, keyword)| {
+            let cipher = build(keyword)?;
+            let plaintext = cipher.decrypt(text);
+            let score = stats.score(lang, &plaintext);
+
+            Some((
+                i,
+                Solution {
+                    key: cipher.to_string(),
+                    keyword: Some(keyword.clone()),
+                    plaintext,
+                    score,
+                },
+            ))
+        })
+        .reduce_with(
+            |a, b| match b.1.score > a.1.score || (b.1.score >= a.1.score && b.0 < a.0) {
+                true => b,
+                false => a,
             },
+        )
+        .map(|(_, solution)| solution)
+        .ok_or_else(|| anyhow::anyhow!("None of the keywords could be used with this cipher"))
+}
+
+/// The words of the word list in uppercase, most common first.
+fn dictionary_words(words: &Words) -> Vec<String> {
+    let mut words = words.iter().collect::<Vec<_>>();
+    words.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(Ordering::Equal)
+            .then(a.0.cmp(b.0))
+    });
+
+    words
+        .into_iter()
+        .map(|(word, _)| word.to_uppercase())
+        .collect()
+}
+
+/// Converts the `--size` and `--word-weight` options to the statistics used
+/// for scoring, loading the quintgrams or words of the lang `name` if they
+/// are needed.
+pub fn stats_or_default(
+    cfg: &CipherConfig,
+    name: Option<String>,
+    stats_size: Option<StatsSizeOpt>,
+    word_weight: Option<f32>,
+) -> anyhow::Result<Stats> {
+    let stats = match stats_size {
+        Some(StatsSizeOpt::Unigrams) => Stats::Lang(StatsSize::Unigrams),
+        Some(StatsSizeOpt::Bigrams) => Stats::Lang(StatsSize::Bigrams),
+        Some(StatsSizeOpt::Trigrams) => Stats::Lang(StatsSize::Trigrams),
+        Some(StatsSizeOpt::Quintgrams) => {
+            Stats::Quintgrams(Arc::new(cfg.load_quintgrams_or_selected(name.clone())?))
+        }
+        Some(StatsSizeOpt::Words) => {
+            return Ok(Stats::Words(Arc::new(cfg.load_words_or_selected(name)?)))
+        }
+        _ => Stats::Lang(StatsSize::Quadgrams),
+    };
+
+    Ok(match word_weight {
+        Some(weight) => Stats::WithWords(
+            Box::new(stats),
+            Arc::new(cfg.load_words_or_selected(name)?),
+            weight,
+        ),
+        None => stats,
+    })
+}
+
+/// Solves `text` without knowing the cipher. Likely ciphers are chosen from
+/// the text's statistics, each is solved, and the solution with the best
+/// scoring plaintext is returned. Candidates which fail to solve are
+/// skipped.
+pub fn solve_auto<'l>(
+    ctx: &SolveContext<'l>,
+    text: &str,
+) -> anyhow::Result<Box<dyn CliCipher + 'l>> {
+    let SolveContext { lang, stats, .. } = *ctx;
+    let alph = lang.with_alphabet(AlphabetLen::Any);
+    let ioc = analysis::ioc(alph.code_points(text).map(|x| x as usize));
+    let monoalphabetic =
+        (alph.expected_ioc() - ioc).abs() / alph.expected_ioc() < AUTO_IOC_TOLERANCE;
+    let layers = Layers::detect(lang, text);
+
+    let candidates = match (monoalphabetic, layers.substitution, layers.transposition) {
+        (false, ..) => vec![CipherSolveCmd::ClassicVigenere { max_key_length: 30 }],
+        (true, false, _) => vec![CipherSolveCmd::Railfence, CipherSolveCmd::Scytale],
+        (true, true, false) => vec![
+            CipherSolveCmd::Caesar,
+            CipherSolveCmd::Affine,
+            CipherSolveCmd::Atbash,
+            CipherSolveCmd::Substitution {
+                max_iterations: 2000,
+                min_repetitions: 5,
+            },
+        ],
+        (true, true, true) => vec![CipherSolveCmd::SubstitutionTransposition {
+            max_period: 20,
+            max_iterations: 2000,
+            min_repetitions: 5,
+        }],
+    };
+
+    let mut best: Option<(f32, Box<dyn CliCipher + 'l>)> = None;
+    let mut last_err = None;
+
+    for candidate in &candidates {
+        debug!("trying {candidate:?}");
+
+        let solution = match solve_cipher(ctx, text, candidate, None, None) {
+            Ok(solution) => solution,
+            Err(err) => {
+                warn!("skipping {candidate:?}: {err}");
+                last_err = Some(err);
+                continue;
+            }
         };
+        let score = stats.score(lang, &solution.decrypt(text));
+
+        match &best {
+            Some((best_score, _)) if *best_score >= score => {}
+            _ => best = Some((score, solution)),
+        }
+    }
+
+    // only fail if every candidate did
+    best.map(|(_, solution)| solution)
+        .ok_or_else(|| last_err.unwrap_or_else(|| anyhow::anyhow!("No cipher could be identified")))
+}
+
+/// Solves `text` as the cipher given by `cipher`.
+pub fn solve_cipher<'l>(
+    ctx: &SolveContext<'l>,
+    text: &str,
+    cipher: &CipherSolveCmd,
+    crib: Option<&str>,
+    crib_pos: Option<usize>,
+) -> anyhow::Result<Box<dyn CliCipher + 'l>> {
+    let SolveContext {
+        lang,
+        letters,
+        stats,
+        search,
+    } = *ctx;
+
+    let n = lang.primary_len() as i32;
+    let len = lang
+        .with_alphabet(AlphabetLen::Any)
+        .code_points(text)
+        .count() as i32;
 
-        if show_key {
-            println!("{}", solution);
+    Ok(match (cipher, stats.size()) {
+        (CipherSolveCmd::Affine, Some(size)) => Box::new(Affine::solve(lang, text, size)),
+        (CipherSolveCmd::Affine, None) => best_key(
+            ctx,
+            text,
+            (1..n).flat_map(|a| (0..n).filter_map(move |b| Affine::new(lang, a, b).ok())),
+        )?,
+        (CipherSolveCmd::Atbash, _) => Box::new(Atbash::solve(lang, text, ())),
+        (CipherSolveCmd::Caesar, Some(size)) => Box::new(Caesar::solve(lang, text, size)),
+        (CipherSolveCmd::Caesar, None) => best_key(
+            ctx,
+            text,
+            (0..n).filter_map(|shift| Caesar::new(lang, shift).ok()),
+        )?,
+        (CipherSolveCmd::Chaocipher { alphabet }, _) => Box::new(Chaocipher::solve(
+            lang,
+            text,
+            ChaocipherSolve {
+                crib: crib.unwrap_or_default(),
+                crib_pos,
+                alphabet: alphabet.as_deref().ok_or(CipherError::NoLetters)?,
+                stats,
+                search,
+            },
+        )?),
+        (&CipherSolveCmd::ClassicVigenere { max_key_length }, Some(stats_size)) => {
+            Box::new(ClassicVigenere::solve(
+                lang,
+                text,
+                ClassicVigenereSolve {
+                    stats_size,
+                    max_key_length,
+                },
+            ))
         }
+        (CipherSolveCmd::ClassicVigenere { .. }, None) => {
+            return Err(CipherError::UnsupportedStats.into())
+        }
+        (CipherSolveCmd::Railfence, Some(size)) => Box::new(Railfence::solve(lang, text, size)),
+        (CipherSolveCmd::Railfence, None) => best_key(
+            ctx,
+            text,
+            (2..len).filter_map(|rails| Railfence::new(lang, rails).ok()),
+        )?,
+        (CipherSolveCmd::Rot13, _) => Box::new(Rot13::solve(lang, text, ())),
+        (CipherSolveCmd::Scytale, Some(size)) => Box::new(Scytale::solve(lang, text, size)),
+        (CipherSolveCmd::Scytale, None) => best_key(
+            ctx,
+            text,
+            (2..len).filter_map(|faces| Scytale::new(lang, faces).ok()),
+        )?,
+        (
+            &CipherSolveCmd::Substitution {
+                max_iterations,
+                min_repetitions,
+            },
+            _,
+        ) => Box::new(partial::solve_substitution(
+            lang,
+            letters,
+            text,
+            PartialSolve {
+                stats: stats.clone(),
+                max_iterations,
+                min_repetitions,
+            },
+            search,
+        )?),
+        (
+            &CipherSolveCmd::SubstitutionTransposition {
+                max_period,
+                max_iterations,
+                min_repetitions,
+            },
+            _,
+        ) => Box::new(layered::solve(
+            lang,
+            text,
+            LayeredSolve {
+                letters,
+                max_period,
+                substitution: PartialSolve {
+                    stats: stats.clone(),
+                    max_iterations,
+                    min_repetitions,
+                },
+                search,
+            },
+        )?),
+    })
+}
+
+/// Tries each of `ciphers` and returns the one whose decryption of `text`
+/// scores highest, for statistics which the solvers from `classic_crypto`
+/// can't use. Earlier keys win ties.
+fn best_key<'l, C>(
+    ctx: &SolveContext<'l>,
+    text: &str,
+    ciphers: impl Iterator<Item = C>,
+) -> anyhow::Result<Box<dyn CliCipher + 'l>>
+where
+    C: CliCipher + 'l,
+{
+    let mut best: Option<(f32, C)> = None;
 
-        if !no_plain {
-            println!("{}", solution.decrypt(text).trim_end());
+    for cipher in ciphers {
+        let score = ctx.stats.score(ctx.lang, &cipher.decrypt(text));
+
+        match &best {
+            Some((best_score, _)) if *best_score >= score => {}
+            _ => best = Some((score, cipher)),
         }
     }
 
-    Ok(())
+    best.map(|(_, cipher)| Box::new(cipher) as _)
+        .ok_or_else(|| anyhow::anyhow!("No key could be tried"))
 }
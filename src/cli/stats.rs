@@ -4,82 +4,94 @@ use classic_crypto::{
 };
 use colorful::{Colorful, HSL};
 
-use crate::{util, CipherConfig, StatsCmd};
+use crate::{cli::IoOpt, util, CipherConfig, StatsCmd};
+use std::io::Write;
 
 pub fn stats(
     cfg: &CipherConfig,
     lang: Option<String>,
     text: Option<String>,
+    io: IoOpt,
     stats_opt: StatsCmd,
 ) -> anyhow::Result<()> {
+    let lang_name = lang.clone();
     let lang = cfg.load_lang_or_selected(lang)?;
-    let text = util::unwrap_or_stdin(text)?;
     let alph = lang.with_alphabet(AlphabetLen::Any);
-    let cp = alph.code_points(&text).collect::<Vec<_>>();
-    let len = cp.len();
+    let texts = util::read_inputs(text, &io.input)?;
+    let mut out = util::output(io.output.as_deref())?;
 
-    match stats_opt {
-        StatsCmd::Periodic { width, alphabet: _ } => {
-            let expected = alph.expected_ioc();
-            let ioc: Vec<_> = (1..len.min(100))
-                .map(|p| analysis::periodic_ioc(cp.iter().map(|&x| x as usize), p))
-                .take_while(|&x| x.is_normal())
-                .collect();
-            let max = ioc.iter().copied().fold(0.0, f32::max);
+    for text in texts {
+        let cp = alph.code_points(&text).collect::<Vec<_>>();
+        let len = cp.len();
 
-            let total_width = width.clamp(20, 100);
-            let bar_width = (total_width - 11) as f32;
+        match stats_opt.clone() {
+            StatsCmd::Periodic { width, alphabet: _ } => {
+                let expected = alph.expected_ioc();
+                let ioc: Vec<_> = (1..len.min(100))
+                    .map(|p| analysis::periodic_ioc(cp.iter().map(|&x| x as usize), p))
+                    .take_while(|&x| x.is_normal())
+                    .collect();
+                let max = ioc.iter().copied().fold(0.0, f32::max);
 
-            //        00 0.00000 ---------------
-            if !ioc.is_empty() {
-                println!(" p ioc     bar");
-            }
+                let total_width = width.clamp(20, 100);
+                let bar_width = (total_width - 11) as f32;
+
+                //        00 0.00000 ---------------
+                if !ioc.is_empty() {
+                    writeln!(out, " p ioc     bar")?;
+                }
 
-            for (period, &ioc) in ioc.iter().enumerate() {
-                let width = ((ioc / max) * bar_width).floor() as usize;
-                let diff_proportion = (expected - ioc).abs() / expected;
+                for (period, &ioc) in ioc.iter().enumerate() {
+                    let width = ((ioc / max) * bar_width).floor() as usize;
+                    let diff_proportion = (expected - ioc).abs() / expected;
 
-                let bar = format!(
-                    "{:>2} {:<6.05} {}",
-                    period + 1,
-                    ioc,
-                    "━"
-                        .repeat(width)
-                        .gradient(HSL::new(1.0 - diff_proportion, 1.0, 0.5))
-                );
+                    let bar = format!(
+                        "{:>2} {:<6.05} {}",
+                        period + 1,
+                        ioc,
+                        "━"
+                            .repeat(width)
+                            .gradient(HSL::new(1.0 - diff_proportion, 1.0, 0.5))
+                    );
 
-                if diff_proportion < 0.2 {
-                    println!("{}", bar.bold());
-                } else {
-                    println!("{}", bar);
+                    if diff_proportion < 0.2 {
+                        writeln!(out, "{}", bar.bold())?;
+                    } else {
+                        writeln!(out, "{}", bar)?;
+                    }
                 }
             }
-        }
-        StatsCmd::Freq {
-            alphabet: _,
-            punct: _,
-            whitespace: _,
-        } => {
-            todo!()
-        }
-        StatsCmd::Length { alphabet: _ } => todo!(),
-        StatsCmd::Ioc { alphabet: _ } => {
-            println!("{}", analysis::ioc(cp.iter().map(|&x| x as usize)));
-        }
-        StatsCmd::ChiSquared => {
-            println!("{}", alph.chi_squared(cp));
-        }
-        StatsCmd::Unigram => {
-            println!("{}", alph.score(cp, StatsSize::Unigrams));
-        }
-        StatsCmd::Bigram => {
-            println!("{}", alph.score(cp, StatsSize::Bigrams));
-        }
-        StatsCmd::Trigram => {
-            println!("{}", alph.score(cp, StatsSize::Trigrams));
-        }
-        StatsCmd::Quadgram => {
-            println!("{}", alph.score(cp, StatsSize::Quadgrams));
+            StatsCmd::Freq {
+                alphabet: _,
+                punct: _,
+                whitespace: _,
+            } => {
+                todo!()
+            }
+            StatsCmd::Length { alphabet: _ } => todo!(),
+            StatsCmd::Ioc { alphabet: _ } => {
+                writeln!(out, "{}", analysis::ioc(cp.iter().map(|&x| x as usize)))?;
+            }
+            StatsCmd::ChiSquared => {
+                writeln!(out, "{}", alph.chi_squared(cp))?;
+            }
+            StatsCmd::Unigram => {
+                writeln!(out, "{}", alph.score(cp, StatsSize::Unigrams))?;
+            }
+            StatsCmd::Bigram => {
+                writeln!(out, "{}", alph.score(cp, StatsSize::Bigrams))?;
+            }
+            StatsCmd::Trigram => {
+                writeln!(out, "{}", alph.score(cp, StatsSize::Trigrams))?;
+            }
+            StatsCmd::Quadgram => {
+                writeln!(out, "{}", alph.score(cp, StatsSize::Quadgrams))?;
+            }
+            StatsCmd::Quintgram => {
+                let quintgrams = cfg.load_quintgrams_or_selected(lang_name.clone())?;
+                let cp = cp.iter().map(|&x| x as usize).collect::<Vec<_>>();
+                writeln!(out, "{}", quintgrams.score(&cp))?;
+            }
         }
     }
 
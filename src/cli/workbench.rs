@@ -0,0 +1,284 @@
+//! An interactive terminal workbench for solving substitution ciphers by
+//! hand, with the solver available to finish the job.
+
+use crate::{
+    cipher::partial::{PartialKey, PartialSolve},
+    cli::solve::stats_or_default,
+    restart::Search,
+    score::Stats,
+    util, CipherConfig, Opt,
+};
+use classic_crypto::{lang::Lang, prelude::StatsSize};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    queue,
+    style::{Print, PrintStyledContent, Stylize},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// Shown in the partial decryption for letters which are not known yet.
+const UNKNOWN: char = '·';
+/// Rows used by the header and footer.
+const RESERVED_ROWS: usize = 9;
+
+const HELP: &str =
+    "type a ciphertext letter then its plaintext letter · space clears · enter finishes with the solver · esc quits";
+
+pub fn workbench(cfg: &CipherConfig, workbench_opt: Opt) -> anyhow::Result<()> {
+    if let Opt::Workbench {
+        stats_size,
+        max_iterations,
+        min_repetitions,
+        lang: lang_name,
+        text,
+        io,
+    } = workbench_opt
+    {
+        let letters = cfg.letters_or_selected(lang_name.clone())?;
+        let stats = stats_or_default(cfg, lang_name.clone(), stats_size, None)?;
+        let lang = &cfg.load_lang_or_selected(lang_name)?;
+        let texts = util::read_inputs(text, &io.input)?;
+        let mut out = util::output(io.output.as_deref())?;
+
+        for text in texts {
+            let mut bench = Workbench {
+                lang,
+                text,
+                key: PartialKey::new(letters.clone()),
+                pending: None,
+                message: String::new(),
+                stats: stats.clone(),
+                max_iterations,
+                min_repetitions,
+            };
+
+            {
+                let _screen = Screen::enter()?;
+                bench.run()?;
+            }
+
+            writeln!(out, "{}", bench.key.key())?;
+            writeln!(out, "{}", bench.key.decrypt(&bench.text, '.'))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Switches the terminal to raw mode on an alternate screen, and restores it
+/// when dropped.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(io::stderr(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        crossterm::execute!(io::stderr(), Show, LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+struct Workbench<'l> {
+    lang: &'l Lang,
+    text: String,
+    key: PartialKey,
+    /// A ciphertext letter waiting for its plaintext letter.
+    pending: Option<char>,
+    message: String,
+    stats: Stats,
+    max_iterations: usize,
+    min_repetitions: usize,
+}
+
+impl Workbench<'_> {
+    /// Handles key presses until the user quits.
+    fn run(&mut self) -> anyhow::Result<()> {
+        let mut screen = io::stderr();
+
+        loop {
+            self.draw(&mut screen)?;
+
+            let (code, modifiers) = match event::read()? {
+                Event::Key(KeyEvent {
+                    code, modifiers, ..
+                }) => (code, modifiers),
+                _ => continue,
+            };
+            self.message.clear();
+
+            match (code, self.pending.take()) {
+                (KeyCode::Char('c'), _) if modifiers.contains(KeyModifiers::CONTROL) => break,
+                (KeyCode::Esc, Some(_)) => {}
+                (KeyCode::Esc, None) => break,
+                (KeyCode::Enter, _) => {
+                    self.message = "solving...".to_string();
+                    self.draw(&mut screen)?;
+
+                    self.key.solve(
+                        self.lang,
+                        &self.text,
+                        PartialSolve {
+                            stats: self.stats.clone(),
+                            max_iterations: self.max_iterations,
+                            min_repetitions: self.min_repetitions,
+                        },
+                        &Search::new(None, false),
+                    );
+                    self.message = "solved the unlocked letters".to_string();
+                }
+                (KeyCode::Char(' ') | KeyCode::Backspace | KeyCode::Delete, Some(cipher)) => {
+                    self.key.clear(cipher);
+                }
+                (KeyCode::Char(plain), Some(cipher)) => match self.key.set(cipher, plain) {
+                    true => {}
+                    false => self.message = format!("{plain} is not a letter of the language"),
+                },
+                (KeyCode::Char(cipher), None) => match self.key.index(cipher) {
+                    Some(i) => self.pending = Some(self.key.letters()[i]),
+                    None => self.message = format!("{cipher} is not a letter of the language"),
+                },
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, screen: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width.max(20) as usize, height as usize);
+        let mut row = 0;
+
+        queue!(screen, Clear(ClearType::All))?;
+
+        let plaintext = self.key.decrypt(&self.text, UNKNOWN);
+        let mut scores = [
+            ("unigram", StatsSize::Unigrams),
+            ("bigram", StatsSize::Bigrams),
+            ("trigram", StatsSize::Trigrams),
+            ("quadgram", StatsSize::Quadgrams),
+        ]
+        .iter()
+        .map(|&(name, size)| {
+            let score = Stats::Lang(size).score(self.lang, &plaintext);
+            format!("{name} {score:.1}")
+        })
+        .collect::<Vec<_>>();
+
+        // statistics which are only loaded when selected
+        let selected = match self.stats {
+            Stats::Quintgrams(_) => Some("quintgram"),
+            Stats::Words(_) => Some("words"),
+            _ => None,
+        };
+
+        if let Some(name) = selected {
+            let score = self.stats.score(self.lang, &plaintext);
+            scores.push(format!("{name} {score:.1}"));
+        }
+
+        queue!(
+            screen,
+            MoveTo(0, row),
+            PrintStyledContent("workbench".bold()),
+            Print(format!("  {}", scores.join(", ")))
+        )?;
+        row += 2;
+
+        // the ciphertext, with its decryption underneath
+        let cipher_lines = self.text.lines().flat_map(|line| chunks(line, width));
+        let plain_lines = plaintext.lines().flat_map(|line| chunks(line, width));
+        let max_rows = height.saturating_sub(RESERVED_ROWS) / 3;
+
+        for (cipher, plain) in cipher_lines.zip(plain_lines).take(max_rows) {
+            queue!(screen, MoveTo(0, row), Print(&cipher), MoveTo(0, row + 1))?;
+
+            for (c, p) in cipher.chars().zip(plain.chars()) {
+                match self.key.get(c) {
+                    Some((_, true)) => queue!(screen, PrintStyledContent(p.green().bold()))?,
+                    Some((_, false)) => queue!(screen, PrintStyledContent(p.yellow()))?,
+                    None => queue!(screen, PrintStyledContent(p.dark_grey()))?,
+                }
+            }
+            row += 3;
+        }
+
+        // the key, as plaintext letters under their ciphertext letters
+        let letters = self.key.letters();
+        let plain_row = letters
+            .iter()
+            .map(|&c| self.key.get(c).map(|(p, _)| p).unwrap_or(UNKNOWN))
+            .collect::<String>();
+
+        queue!(
+            screen,
+            MoveTo(0, row),
+            Print(format!("cipher  {}", letters.iter().collect::<String>())),
+            MoveTo(0, row + 1),
+            Print(format!("plain   {plain_row}")),
+            MoveTo(0, row + 2),
+            Print(format!("freq    {}", self.frequencies(width - 8))),
+        )?;
+        row += 4;
+
+        let status = match self.pending {
+            Some(cipher) => format!("{cipher} → ?"),
+            None => self.message.clone(),
+        };
+
+        queue!(
+            screen,
+            MoveTo(0, row),
+            PrintStyledContent(status.bold()),
+            MoveTo(0, row + 1),
+            PrintStyledContent(HELP.chars().take(width).collect::<String>().dark_grey()),
+        )?;
+
+        screen.flush()
+    }
+
+    /// The ciphertext letters, most frequent first, with their counts.
+    fn frequencies(&self, width: usize) -> String {
+        let mut counts = HashMap::new();
+        for c in self.text.chars() {
+            if let Some(i) = self.key.index(c) {
+                *counts.entry(self.key.letters()[i]).or_insert(0usize) += 1;
+            }
+        }
+
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut freqs = String::new();
+        for (c, n) in counts {
+            let entry = format!("{c}:{n} ");
+            if freqs.chars().count() + entry.chars().count() > width {
+                break;
+            }
+            freqs.push_str(&entry);
+        }
+
+        freqs
+    }
+}
+
+/// Splits a line into pieces of at most `width` characters.
+fn chunks(line: &str, width: usize) -> Vec<String> {
+    let chars = line.chars().collect::<Vec<_>>();
+
+    match chars.is_empty() {
+        true => vec![String::new()],
+        false => chars.chunks(width).map(|c| c.iter().collect()).collect(),
+    }
+}
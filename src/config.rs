@@ -5,7 +5,15 @@ use log::{debug, trace};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, io::Read, path::PathBuf};
 
-use crate::{cli::segment::Words, error::CipherError};
+use crate::{
+    alphabet::Alphabet,
+    cli::{
+        corpus::Counts,
+        segment::{Words, UNKNOWN_PENALTY, UNKNOWN_WEIGHT},
+    },
+    error::CipherError,
+    score::Quintgrams,
+};
 
 const QUALIFIER: &str = "";
 const ORGANISATION: &str = "";
@@ -28,6 +36,23 @@ pub struct LangMeta {
     pub primary: usize,
     // lengths of the alphabets
     pub alphabets: Vec<usize>,
+    // letters of the alphabet given when the lang was added
+    #[serde(default)]
+    pub upper: String,
+    #[serde(default)]
+    pub lower: String,
+    // parameters of the model which scores words not in the word list
+    #[serde(default = "unknown_penalty")]
+    pub unknown_penalty: f32,
+    #[serde(default = "unknown_weight")]
+    pub unknown_weight: f32,
+}
+
+fn unknown_penalty() -> f32 {
+    UNKNOWN_PENALTY
+}
+fn unknown_weight() -> f32 {
+    UNKNOWN_WEIGHT
 }
 
 impl CipherConfig {
@@ -42,7 +67,7 @@ impl CipherConfig {
         let mut lang = self.load_lang(&name)?;
         lang.set_primary(length)?;
 
-        let mut meta = self.lang_map.get_mut(&name).unwrap();
+        let meta = self.lang_map.get_mut(&name).unwrap();
         meta.primary = length;
 
         let path = Self::lang_file_path(self.lang_map[&*name].id)?;
@@ -76,6 +101,35 @@ impl CipherConfig {
             None => self.load_selected_words(),
         }
     }
+    /// The uppercase letters of the language `name`, or if that fails, of
+    /// the selected language.
+    pub fn letters_or_selected(&self, name: Option<String>) -> Result<Vec<char>> {
+        let meta = self.lang_meta_or_selected(name)?;
+
+        match meta.upper.is_empty() {
+            true => Err(CipherError::NoLetters.into()),
+            false => Ok(meta.upper.chars().collect()),
+        }
+    }
+    /// The alphabet of the language `name`, or if that fails, of the
+    /// selected language.
+    pub fn alphabet_or_selected(&self, name: Option<String>) -> Result<Alphabet> {
+        let meta = self.lang_meta_or_selected(name)?;
+
+        match meta.upper.is_empty() {
+            true => Err(CipherError::NoLetters.into()),
+            false => Ok(Alphabet::new(&meta.upper, &meta.lower)),
+        }
+    }
+    fn lang_meta_or_selected(&self, name: Option<String>) -> Result<&LangMeta> {
+        match name.as_deref().and_then(|name| self.lang_meta(name)) {
+            Some(meta) => Ok(meta),
+            None => self
+                .selected_lang()
+                .and_then(|name| self.lang_meta(name))
+                .ok_or_else(|| CipherError::NoLangSelected.into()),
+        }
+    }
     /// Metadata for the language
     pub fn lang_meta(&self, name: &str) -> Option<&LangMeta> {
         self.lang_map.get(name.trim())
@@ -97,7 +151,15 @@ impl CipherConfig {
         )
     }
     /// Adds a language file by name
-    pub fn add_lang(&mut self, name: String, lang: &Lang, words: &Words) -> Result<()> {
+    pub fn add_lang(
+        &mut self,
+        name: String,
+        (upper, lower): (&str, &str),
+        lang: &Lang,
+        words: &Words,
+        quintgrams: &Quintgrams,
+        counts: &Counts,
+    ) -> Result<()> {
         // check whether name already exists
         let name = name.trim();
         if self.lang_map.contains_key(name) {
@@ -105,20 +167,67 @@ impl CipherConfig {
         }
 
         // insert at new id
-        let id = self.lang_map.values().map(|m| m.id).max().unwrap_or(0);
+        let id = self
+            .lang_map
+            .values()
+            .map(|m| m.id)
+            .max()
+            .map_or(0, |id| id + 1);
         self.lang_map.insert(
             name.to_string(),
             LangMeta {
                 id,
                 primary: lang.primary_len(),
                 alphabets: lang.alphabets().map(|a| a.alphabet_len()).collect(),
+                upper: upper.to_string(),
+                lower: lower.to_string(),
+                unknown_penalty: UNKNOWN_PENALTY,
+                unknown_weight: UNKNOWN_WEIGHT,
             },
         );
 
         // create directory
         fs::create_dir_all(Self::lang_dir()?)?;
+        Self::write_lang(id, lang, words, quintgrams, counts)
+    }
+    /// Replaces the statistics of the language `name`, keeping its selected
+    /// alphabet.
+    pub fn update_lang(
+        &mut self,
+        name: &str,
+        mut lang: Lang,
+        words: &Words,
+        quintgrams: &Quintgrams,
+        counts: &Counts,
+    ) -> Result<()> {
+        let meta = self
+            .lang_map
+            .get_mut(name.trim())
+            .ok_or(CipherError::LangNotFound)?;
+
+        lang.set_primary(meta.primary)?;
+        meta.alphabets = lang.alphabets().map(|a| a.alphabet_len()).collect();
+
+        Self::write_lang(meta.id, &lang, words, quintgrams, counts)
+    }
+    fn write_lang(
+        id: usize,
+        lang: &Lang,
+        words: &Words,
+        quintgrams: &Quintgrams,
+        counts: &Counts,
+    ) -> Result<()> {
         fs::write(Self::lang_file_path(id)?, bincode::serialize(lang)?)?;
         fs::write(Self::words_file_path(id)?, bincode::serialize(words)?)?;
+        fs::write(
+            Self::word_bigrams_file_path(id)?,
+            bincode::serialize(words.bigrams())?,
+        )?;
+        fs::write(
+            Self::quintgrams_file_path(id)?,
+            bincode::serialize(quintgrams)?,
+        )?;
+        fs::write(Self::counts_file_path(id)?, bincode::serialize(counts)?)?;
 
         Ok(())
     }
@@ -139,6 +248,18 @@ impl CipherConfig {
         fs::remove_file(Self::lang_file_path(id)?)?;
         fs::remove_file(Self::words_file_path(id)?)?;
 
+        // langs added before quintgrams, word bigrams and counts were kept do
+        // not have them
+        for path in [
+            Self::quintgrams_file_path(id)?,
+            Self::word_bigrams_file_path(id)?,
+            Self::counts_file_path(id)?,
+        ] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
         // remove from preferred
         if self.selected_lang.as_deref() == Some(name) {
             self.selected_lang = None;
@@ -159,9 +280,96 @@ impl CipherConfig {
     pub fn load_words(&self, name: &str) -> Result<Words> {
         let id = self.lookup(name)?;
         let bytes = fs::read(Self::words_file_path(id)?)?;
-        let words = bincode::deserialize(&bytes)?;
+        let mut words: Words = match bincode::deserialize(&bytes) {
+            Ok(words) => words,
+            // word lists saved before counts were kept
+            Err(_) => Words::from_scores(bincode::deserialize(&bytes)?),
+        };
+
+        // langs added before their letters were recorded use any letter
+        if let Ok(alphabet) = self.alphabet_or_selected(Some(name.to_string())) {
+            words.set_alphabet(alphabet);
+        }
+
+        // and those added before word bigrams were counted use unigrams
+        if let Ok(bytes) = fs::read(Self::word_bigrams_file_path(id)?) {
+            words.set_bigrams(bincode::deserialize(&bytes)?);
+        }
+
+        if let Some(meta) = self.lang_meta(name) {
+            words.fit_unknown(meta.unknown_penalty, meta.unknown_weight);
+        }
+
         Ok(words)
     }
+    /// Changes the parameters of the unknown word model of the language
+    /// `name`, returning them.
+    pub fn set_unknown_words(
+        &mut self,
+        name: &str,
+        penalty: Option<f32>,
+        weight: Option<f32>,
+    ) -> Result<(f32, f32)> {
+        let meta = self
+            .lang_map
+            .get_mut(name.trim())
+            .ok_or(CipherError::LangNotFound)?;
+
+        meta.unknown_penalty = penalty.unwrap_or(meta.unknown_penalty);
+        meta.unknown_weight = weight.unwrap_or(meta.unknown_weight);
+
+        Ok((meta.unknown_penalty, meta.unknown_weight))
+    }
+    /// The upper and lower case letters of the language `name`.
+    pub fn letters(&self, name: &str) -> Result<(String, String)> {
+        let meta = self.lang_meta(name).ok_or(CipherError::LangNotFound)?;
+
+        match meta.upper.is_empty() {
+            true => Err(CipherError::NoLetters.into()),
+            false => Ok((meta.upper.clone(), meta.lower.clone())),
+        }
+    }
+    /// Loads the counts which the language `name` was built from, with the
+    /// word counts from its word list.
+    pub fn load_counts(&self, name: &str) -> Result<Counts> {
+        let id = self.lookup(name)?;
+        let bytes = fs::read(Self::counts_file_path(id)?).map_err(|_| CipherError::NoCounts)?;
+        let mut counts: Counts = bincode::deserialize(&bytes)?;
+
+        counts.words = self
+            .load_words(name)?
+            .counts()
+            .map(|(word, count)| (word.to_string(), count))
+            .collect();
+
+        Ok(counts)
+    }
+    /// Replaces the word list and word bigrams of the language `name`.
+    pub fn save_words(&self, name: &str, words: &Words) -> Result<()> {
+        let id = self.lookup(name)?;
+        fs::write(Self::words_file_path(id)?, bincode::serialize(words)?)?;
+        fs::write(
+            Self::word_bigrams_file_path(id)?,
+            bincode::serialize(words.bigrams())?,
+        )?;
+        Ok(())
+    }
+    /// Loads the quintgrams for the language `name`, or if that fails, for
+    /// the selected language.
+    pub fn load_quintgrams_or_selected(&self, name: Option<String>) -> Result<Quintgrams> {
+        let id = match name.as_deref().map(|name| self.lookup(name)) {
+            Some(Ok(id)) => id,
+            _ => self.lookup(
+                self.selected_lang
+                    .as_ref()
+                    .ok_or(CipherError::NoLangSelected)?,
+            )?,
+        };
+
+        let bytes =
+            fs::read(Self::quintgrams_file_path(id)?).map_err(|_| CipherError::NoQuintgrams)?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
 
     /// Lookup the language name, get the id.
     fn lookup(&self, name: &str) -> Result<usize> {
@@ -236,6 +444,15 @@ impl CipherConfig {
     fn words_file_path(id: usize) -> Result<PathBuf> {
         Self::lang_dir().map(|cfg| cfg.join(format!("{id}.words")))
     }
+    fn word_bigrams_file_path(id: usize) -> Result<PathBuf> {
+        Self::lang_dir().map(|cfg| cfg.join(format!("{id}.wbigrams")))
+    }
+    fn counts_file_path(id: usize) -> Result<PathBuf> {
+        Self::lang_dir().map(|cfg| cfg.join(format!("{id}.counts")))
+    }
+    fn quintgrams_file_path(id: usize) -> Result<PathBuf> {
+        Self::lang_dir().map(|cfg| cfg.join(format!("{id}.quint")))
+    }
     fn config_path() -> Result<PathBuf> {
         ProjectDirs::from(QUALIFIER, ORGANISATION, APPLICATION)
             .map(|dirs| dirs.config_dir().to_owned())
@@ -5,6 +5,19 @@ pub enum CipherError {
     LangAlreadyExists,
     LangNotFound,
     NoLangSelected,
+    InvalidAlphabet,
+    MissingCrib,
+    CribInconsistent,
+    CribSearchLimit,
+    NoCipher,
+    NoLetters,
+    NoDictionaryAttack,
+    NoQuintgrams,
+    UnsupportedStats,
+    InvalidFrequencies,
+    NoWordSources,
+    NoCounts,
+    NoLayers,
 }
 
 impl std::error::Error for CipherError {}
@@ -18,6 +31,32 @@ impl fmt::Display for CipherError {
                 CipherError::LangNotFound => "Lang with specified name did not exist",
                 CipherError::NoLangSelected =>
                     "No language was selected.\n\ttry `cipher lang set -n <name>`",
+                CipherError::InvalidAlphabet =>
+                    "Alphabets must contain the same letters, each exactly once",
+                CipherError::MissingCrib =>
+                    "A crib is required to solve this cipher.\n\ttry `--crib <text>`",
+                CipherError::CribInconsistent =>
+                    "The crib is not consistent with the ciphertext at any position",
+                CipherError::CribSearchLimit =>
+                    "The crib could not be placed within the search limit.\n\ttry giving its position with `--crib-pos`, or a longer crib",
+                CipherError::NoCipher =>
+                    "No cipher was given.\n\ttry a cipher subcommand, `--stage <spec>` or `--pipeline <file>`",
+                CipherError::NoDictionaryAttack =>
+                    "Only ciphers keyed by a keyword can be solved with a dictionary.\n\ttry `solve classic-vigenere` or `solve substitution`",
+                CipherError::NoQuintgrams =>
+                    "The lang has no quintgram statistics.\n\ttry adding it again with `cipher lang new`",
+                CipherError::UnsupportedStats =>
+                    "This cipher can only be solved with the lang's n-grams, up to quadgrams.\n\ttry a smaller `--size`, without `--word-weight`",
+                CipherError::InvalidFrequencies =>
+                    "Each line of a frequency file must be a word and its count, separated by a tab",
+                CipherError::NoWordSources =>
+                    "No words were given to import.\n\ttry `--freqs <file>` or `--dictionary <file>`",
+                CipherError::NoCounts =>
+                    "The lang does not store the counts it was built from.\n\ttry adding it again with `cipher lang new`",
+                CipherError::NoLayers =>
+                    "Neither a substitution nor a transposition was detected in the ciphertext",
+                CipherError::NoLetters =>
+                    "The lang does not record its letters.\n\ttry adding it again with `cipher lang new`",
             }
         )
     }
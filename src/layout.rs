@@ -0,0 +1,114 @@
+//! Lays out ciphertext and plaintext for display, e.g. in the blocks of five
+//! letters used by cipher competitions.
+
+use crate::cli::{CaseOpt, LayoutOpt};
+
+/// Applies the layout options to `text`. `is_letter` decides which
+/// characters are kept by `--strip`.
+pub fn layout(opt: &LayoutOpt, text: &str, is_letter: impl Fn(char) -> bool) -> String {
+    let mut text = match opt.strip {
+        true => text.chars().filter(|&c| is_letter(c)).collect(),
+        false => text.trim_end().to_string(),
+    };
+
+    match opt.case {
+        Some(CaseOpt::Upper) => text = text.to_uppercase(),
+        Some(CaseOpt::Lower) => text = text.to_lowercase(),
+        None => {}
+    }
+
+    if let Some(size) = opt.group.filter(|&size| size > 0) {
+        text = group(&text, size);
+    }
+
+    if let Some(width) = opt.width.filter(|&width| width > 0) {
+        text = wrap(&text, width);
+    }
+
+    if opt.line_numbers {
+        text = number_lines(&text);
+    }
+
+    text
+}
+
+/// Splits the non-whitespace characters of `text` into blocks of `size`.
+fn group(text: &str, size: usize) -> String {
+    let chars = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+
+    chars
+        .chunks(size)
+        .map(|block| block.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Wraps each line of `text` at whitespace so that no line is longer than
+/// `width`. Words longer than `width` are split.
+fn wrap(text: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+
+    for line in text.lines() {
+        let mut current = String::new();
+        let mut current_len = 0;
+
+        for word in line.split_whitespace() {
+            let word = word.chars().collect::<Vec<_>>();
+
+            for part in word.chunks(width) {
+                if current_len > 0 && current_len + 1 + part.len() > width {
+                    lines.push(std::mem::take(&mut current));
+                    current_len = 0;
+                }
+                if current_len > 0 {
+                    current.push(' ');
+                    current_len += 1;
+                }
+
+                current.extend(part);
+                current_len += part.len();
+            }
+        }
+
+        lines.push(current);
+    }
+
+    lines.join("\n")
+}
+
+/// Prefixes each line with its (right aligned) line number.
+fn number_lines(text: &str) -> String {
+    let count = text.lines().count();
+    let digits = count.to_string().len();
+
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:>digits$} {line}", i + 1))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_ignores_whitespace() {
+        assert_eq!(group("abc de\nfghij kl", 5), "abcde fghij kl");
+        assert_eq!(group("", 5), "");
+    }
+
+    #[test]
+    fn wrap_breaks_at_whitespace() {
+        assert_eq!(wrap("abcde fghij kl", 11), "abcde fghij\nkl");
+        assert_eq!(wrap("ab cd\nef", 80), "ab cd\nef");
+    }
+
+    #[test]
+    fn wrap_splits_long_words() {
+        assert_eq!(wrap("abcdefgh ij", 3), "abc\ndef\ngh\nij");
+    }
+}
@@ -1,11 +1,15 @@
 //! A command line program for encrypting, decrypting, analysing and
 //! breaking classical ciphers.
 
-use classic_crypto::lang::Lang;
-
+mod alphabet;
+mod cipher;
+mod clean;
 mod cli;
 mod config;
 mod error;
+mod layout;
+mod restart;
+mod score;
 mod util;
 
 use clap::Parser;
@@ -18,8 +22,22 @@ fn main() -> anyhow::Result<()> {
     let mut cfg = CipherConfig::load();
 
     match Opt::parse() {
-        Opt::Corpus { file, out } => corpus::corpus(file, out),
-        Opt::Segment { text, lang } => segment::segment(&cfg, text, lang),
+        Opt::Corpus {
+            file,
+            out,
+            lang,
+            upper,
+            lower,
+            clean,
+        } => corpus::corpus(&cfg, file, out, lang, upper.zip(lower), clean),
+        Opt::Segment {
+            text,
+            lang,
+            n_best,
+            show_scores,
+            keep_format,
+            io,
+        } => segment::segment(&cfg, text, lang, n_best, show_scores, keep_format, io),
         Opt::Completions { output, shell } => {
             completions::completions(output, shell);
             Ok(())
@@ -28,7 +46,26 @@ fn main() -> anyhow::Result<()> {
         Opt::Decrypt(decrypt_opt) => crypt::decrypt(&cfg, decrypt_opt),
         Opt::Lang { sub } => lang::lang(&mut cfg, sub),
         solve @ Opt::Solve { .. } => solve::solve(&cfg, solve),
-        Opt::Stats { lang, text, cmd } => stats::stats(&cfg, lang, text, cmd),
+        Opt::Keyword {
+            alphabet,
+            all,
+            lang,
+        } => keyword::keyword(&cfg, alphabet, all, lang),
+        workbench @ Opt::Workbench { .. } => workbench::workbench(&cfg, workbench),
+        Opt::Batch {
+            cipher,
+            input,
+            output,
+            jobs,
+            stats_size,
+            lang,
+        } => batch::batch(&cfg, cipher, input, output, jobs, stats_size, lang),
+        Opt::Stats {
+            lang,
+            text,
+            io,
+            cmd,
+        } => stats::stats(&cfg, lang, text, io, cmd),
     }?;
 
     cfg.save()?;
@@ -0,0 +1,283 @@
+//! Runs independent solver restarts in parallel.
+
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Longest plaintext preview shown on the progress line.
+const PREVIEW_LEN: usize = 40;
+/// Shortest time between redraws of the progress line.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Controls how many restarts are run.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    /// Number of restarts to run at once.
+    pub threads: usize,
+    /// If present, restart `n` seeds its thread's random number generator
+    /// with `seed + n`, so the result is reproducible. Solvers which draw
+    /// random numbers on other threads aren't, nor are restarts run until a
+    /// `timeout`, since how many run depends on the time they take.
+    pub seed: Option<u64>,
+    /// If present, restarts are run until this much time has passed, rather
+    /// than once per thread. Searches which are still running when the time
+    /// runs out stop with the best result they have found.
+    pub timeout: Option<Duration>,
+    /// Show the number of restarts and search iterations, and the best
+    /// result so far on stderr.
+    pub progress: bool,
+    /// Whether the solver uses random numbers. If not, every restart would
+    /// give the same result, so only one is run.
+    pub random: bool,
+}
+
+/// A result which can be compared with and shown alongside other restarts.
+pub trait Scored {
+    fn score(&self) -> f32;
+    fn preview(&self) -> &str;
+}
+
+/// Shared by the restarts of a solve, so that their search loops can stop
+/// when the time runs out, and report their progress.
+pub struct Search {
+    deadline: Option<Instant>,
+    restarts: AtomicU64,
+    iterations: AtomicU64,
+    /// Present if the progress line is shown.
+    progress: Option<Mutex<Progress>>,
+}
+
+/// The best result reported to a [`Search`], and when it was last shown.
+#[derive(Default)]
+struct Progress {
+    best: Option<(f32, String)>,
+    shown: Option<Instant>,
+}
+
+impl Search {
+    /// Creates a search which stops at `deadline`, showing its progress on
+    /// stderr if `progress` is set.
+    pub fn new(deadline: Option<Instant>, progress: bool) -> Self {
+        Self {
+            deadline,
+            restarts: AtomicU64::new(0),
+            iterations: AtomicU64::new(0),
+            progress: progress.then(|| Mutex::new(Progress::default())),
+        }
+    }
+
+    /// Whether the time has run out, so the search should stop with the
+    /// best result it has.
+    pub fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Counts an iteration of a search loop.
+    pub fn iteration(&self) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+        self.show(|_| {});
+    }
+
+    /// Reports a result, which is shown if it scores higher than any before
+    /// it. `preview` is only called if so.
+    pub fn result(&self, score: f32, preview: impl FnOnce() -> String) {
+        self.show(|progress| {
+            if progress.best.as_ref().is_none_or(|(best, _)| score > *best) {
+                progress.best = Some((score, preview()));
+            }
+        });
+    }
+
+    /// Updates the progress, if it is shown, and redraws the progress line
+    /// if it is due.
+    fn show(&self, update: impl FnOnce(&mut Progress)) {
+        let progress = match &self.progress {
+            Some(progress) => progress,
+            None => return,
+        };
+        let mut progress = progress.lock().unwrap_or_else(|e| e.into_inner());
+        update(&mut progress);
+
+        let due = match progress.shown {
+            Some(shown) => shown.elapsed() >= PROGRESS_INTERVAL,
+            None => true,
+        };
+
+        if due && progress.best.is_some() {
+            self.draw(&progress);
+            progress.shown = Some(Instant::now());
+        }
+    }
+
+    /// Overwrites the progress line on stderr, once there is a result to
+    /// show.
+    fn draw(&self, progress: &Progress) {
+        let (score, preview) = match &progress.best {
+            Some((score, preview)) => (score, preview),
+            None => return,
+        };
+        let preview = preview
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(PREVIEW_LEN)
+            .collect::<String>();
+
+        eprint!(
+            "\r\x1b[2Krestarts: {}, iterations: {}, best: {score:.2}, {preview}",
+            self.restarts.load(Ordering::Relaxed),
+            self.iterations.load(Ordering::Relaxed),
+        );
+        io::stderr().flush().ok();
+    }
+
+    /// Redraws the progress line for the last time, if it is shown.
+    fn finish(&self) {
+        if let Some(progress) = &self.progress {
+            self.draw(&progress.lock().unwrap_or_else(|e| e.into_inner()));
+            eprintln!();
+        }
+    }
+}
+
+/// Runs restarts of `run` within `budget` and returns the result with the
+/// highest score. Ties are broken in favour of the earliest restart. Each
+/// restart is given the [`Search`] shared between them.
+pub fn best_of<T>(
+    budget: Budget,
+    run: impl Fn(&Search) -> anyhow::Result<T> + Sync,
+) -> anyhow::Result<T>
+where
+    T: Scored + Send,
+{
+    let threads = match budget.random {
+        true => budget.threads.max(1) as u64,
+        false => 1,
+    };
+    // a timeout too large to represent never expires
+    let deadline = budget
+        .timeout
+        .and_then(|timeout| Instant::now().checked_add(timeout));
+    let search = Search::new(deadline, budget.progress);
+    let next = AtomicU64::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (next, run, search) = (&next, &run, &search);
+
+            s.spawn(move || loop {
+                let n = next.fetch_add(1, Ordering::Relaxed);
+                let finished = match deadline {
+                    Some(_) if budget.random => search.expired(),
+                    _ => n >= threads,
+                };
+
+                // the first restart always runs, so there is a result
+                if finished && n > 0 {
+                    break;
+                }
+
+                if let Some(seed) = budget.seed {
+                    fastrand::seed(seed.wrapping_add(n));
+                }
+
+                if tx.send((n, run(search))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut best: Option<(f32, u64, T)> = None;
+        let mut error = None;
+
+        for (n, result) in rx {
+            search.restarts.fetch_add(1, Ordering::Relaxed);
+
+            match result {
+                Ok(result) => {
+                    let score = result.score();
+                    search.result(score, || result.preview().to_string());
+
+                    let better = match &best {
+                        Some((best_score, best_n, _)) => {
+                            score > *best_score || (score >= *best_score && n < *best_n)
+                        }
+                        None => true,
+                    };
+
+                    if better {
+                        best = Some((score, n, result));
+                    }
+                }
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+
+        search.finish();
+
+        match (best, error) {
+            (Some((_, _, result)), _) => Ok(result),
+            (None, Some(e)) => Err(e),
+            (None, None) => unreachable!("at least one restart is always run"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Draw(f32, String);
+
+    impl Scored for Draw {
+        fn score(&self) -> f32 {
+            self.0
+        }
+
+        fn preview(&self) -> &str {
+            &self.1
+        }
+    }
+
+    fn solve(seed: u64) -> f32 {
+        let budget = Budget {
+            threads: 4,
+            seed: Some(seed),
+            timeout: None,
+            progress: false,
+            random: true,
+        };
+
+        best_of(budget, |_| Ok(Draw(fastrand::f32(), String::new())))
+            .unwrap()
+            .score()
+    }
+
+    #[test]
+    fn same_seed_same_result() {
+        assert_eq!(solve(7), solve(7));
+        assert_ne!(solve(7), solve(100));
+    }
+
+    #[test]
+    fn huge_timeout_never_expires() {
+        let budget = Budget {
+            threads: 1,
+            seed: None,
+            timeout: Some(Duration::MAX),
+            progress: false,
+            random: false,
+        };
+
+        assert!(best_of(budget, |_| Ok(Draw(1.0, String::new()))).is_ok());
+    }
+}
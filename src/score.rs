@@ -0,0 +1,112 @@
+//! Scoring of possible plaintexts.
+
+use crate::cli::segment::{self, Words};
+use classic_crypto::lang::{with_alphabet::StatsSize, AlphabetLen, Lang};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+/// Bits used by each code point in a quintgram key.
+const CODE_POINT_BITS: u32 = 12;
+
+/// Log probabilities of the quintgrams of a language. `classic_crypto` only
+/// provides statistics up to quadgrams, so these are stored alongside the
+/// lang.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Quintgrams {
+    log_probs: HashMap<u64, f32>,
+    /// Log probability of a quintgram which is not in the corpus.
+    floor: f32,
+}
+
+impl Quintgrams {
+    /// Creates the quintgrams from the number of times each sequence of five
+    /// letters was seen.
+    pub fn from_counts(lang: &Lang, quintgrams: &HashMap<String, usize>) -> Self {
+        let alph = lang.with_alphabet(AlphabetLen::Any);
+
+        let mut counts = HashMap::new();
+        for (quintgram, &n) in quintgrams {
+            let cp = alph
+                .code_points(quintgram)
+                .map(|x| x as usize)
+                .collect::<Vec<_>>();
+
+            if cp.len() == 5 {
+                *counts.entry(key(&cp)).or_insert(0usize) += n;
+            }
+        }
+
+        let total = counts.values().sum::<usize>().max(1) as f32;
+
+        Self {
+            log_probs: counts
+                .into_iter()
+                .map(|(k, n)| (k, (n as f32 / total).log10()))
+                .collect(),
+            floor: (0.01 / total).log10(),
+        }
+    }
+
+    /// Sum of the log probabilities of the quintgrams in `cp`.
+    pub fn score(&self, cp: &[usize]) -> f32 {
+        cp.windows(5)
+            .map(|quintgram| {
+                self.log_probs
+                    .get(&key(quintgram))
+                    .copied()
+                    .unwrap_or(self.floor)
+            })
+            .sum()
+    }
+}
+
+/// Packs the code points of a quintgram into a single key.
+fn key(quintgram: &[usize]) -> u64 {
+    quintgram
+        .iter()
+        .fold(0, |key, &x| (key << CODE_POINT_BITS) | x as u64)
+}
+
+/// The statistics used to score plaintexts.
+#[derive(Debug, Clone)]
+pub enum Stats {
+    Lang(StatsSize),
+    Quintgrams(Arc<Quintgrams>),
+    /// The log likelihood of the words in the segmented plaintext.
+    Words(Arc<Words>),
+    /// N-gram statistics with the word log likelihood added, multiplied by
+    /// the weight.
+    WithWords(Box<Stats>, Arc<Words>, f32),
+}
+
+impl Stats {
+    /// The statistics to give to the solvers from `classic_crypto`, which
+    /// only support the n-grams of the lang. `None` if these statistics must
+    /// drive the search themselves.
+    pub fn size(&self) -> Option<StatsSize> {
+        match self {
+            Stats::Lang(size) => Some(*size),
+            Stats::Quintgrams(_) | Stats::Words(_) | Stats::WithWords(..) => None,
+        }
+    }
+
+    /// Scores a possible plaintext. Higher scores are more likely to be
+    /// correct.
+    pub fn score(&self, lang: &Lang, text: &str) -> f32 {
+        let alph = lang.with_alphabet(AlphabetLen::Any);
+
+        match self {
+            Stats::Lang(size) => alph.score(alph.code_points(text).collect(), *size),
+            Stats::Quintgrams(quintgrams) => quintgrams.score(
+                &alph
+                    .code_points(text)
+                    .map(|x| x as usize)
+                    .collect::<Vec<_>>(),
+            ),
+            Stats::Words(words) => segment::log_likelihood(text, words),
+            Stats::WithWords(stats, words, weight) => {
+                stats.score(lang, text) + weight * segment::log_likelihood(text, words)
+            }
+        }
+    }
+}
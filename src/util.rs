@@ -1,4 +1,9 @@
-use std::io::{self, Read};
+use classic_crypto::lang::{AlphabetLen, Lang};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
 
 pub fn stdin_to_string() -> anyhow::Result<String> {
     let mut buf = String::new();
@@ -12,3 +17,41 @@ pub fn unwrap_or_stdin(text: Option<String>) -> anyhow::Result<String> {
         None => stdin_to_string(),
     }
 }
+
+/// Reads the texts to process: `text` if present, otherwise each of the
+/// `inputs` (where `-` is stdin), otherwise stdin.
+pub fn read_inputs(text: Option<String>, inputs: &[PathBuf]) -> anyhow::Result<Vec<String>> {
+    if text.is_some() || inputs.is_empty() {
+        return Ok(vec![unwrap_or_stdin(text)?]);
+    }
+
+    inputs
+        .iter()
+        .map(|path| match path.to_str() {
+            Some("-") => stdin_to_string(),
+            _ => Ok(fs::read_to_string(path)?),
+        })
+        .collect()
+}
+
+/// Opens the file at `path` for writing, or stdout if there is no path or
+/// it is `-`.
+pub fn output(path: Option<&Path>) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) if path.to_str() != Some("-") => {
+            Box::new(io::BufWriter::new(fs::File::create(path)?))
+        }
+        _ => Box::new(io::stdout()),
+    })
+}
+
+/// Returns a function which decides whether a character is a letter of the
+/// language.
+pub fn is_letter(lang: &Lang) -> impl Fn(char) -> bool + '_ {
+    let alph = lang.with_alphabet(AlphabetLen::Any);
+    move |c| {
+        alph.code_points(c.encode_utf8(&mut [0; 4]))
+            .next()
+            .is_some()
+    }
+}